@@ -0,0 +1,52 @@
+CREATE TABLE IF NOT EXISTS task (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    title         TEXT NOT NULL,
+    notes         TEXT,
+    is_active     INTEGER NOT NULL DEFAULT 1,
+    created_at    INTEGER NOT NULL DEFAULT (unixepoch()),
+    archived_at   INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS task_schedule (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id         INTEGER NOT NULL,
+    effective_from  INTEGER NOT NULL,
+    effective_to    INTEGER,
+    type            TEXT NOT NULL CHECK(type IN ('daily','weekly','monthly','custom')),
+    weekday_mask    INTEGER,
+    monthday        INTEGER CHECK(monthday BETWEEN 1 AND 28),
+    interval_days   INTEGER,
+    params_json     TEXT,
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_schedule_task_effective
+    ON task_schedule(task_id, effective_from, effective_to);
+
+CREATE TABLE IF NOT EXISTS task_completion (
+    task_id   INTEGER NOT NULL,
+    day       INTEGER NOT NULL,
+    status    INTEGER NOT NULL CHECK(status IN (1,2)),
+    done_at   INTEGER,
+    PRIMARY KEY (task_id, day),
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_completion_day ON task_completion(day);
+
+CREATE TABLE IF NOT EXISTS task_stats (
+    task_id            INTEGER PRIMARY KEY,
+    current_streak     INTEGER NOT NULL DEFAULT 0,
+    best_streak        INTEGER NOT NULL DEFAULT 0,
+    last_completed_day INTEGER,
+    updated_at         INTEGER NOT NULL DEFAULT (unixepoch()),
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS week_stats (
+    week_start_day  INTEGER PRIMARY KEY,
+    all_done        INTEGER NOT NULL DEFAULT 0,
+    due_count       INTEGER NOT NULL DEFAULT 0,
+    done_count      INTEGER NOT NULL DEFAULT 0,
+    updated_at      INTEGER NOT NULL DEFAULT (unixepoch())
+);
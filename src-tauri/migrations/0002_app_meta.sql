@@ -0,0 +1,6 @@
+-- Small key/value store for facts about the data itself, e.g. which clock
+-- its day indices were computed with.
+CREATE TABLE IF NOT EXISTS app_meta (
+    key    TEXT PRIMARY KEY,
+    value  TEXT NOT NULL
+);
@@ -0,0 +1,23 @@
+-- Per-task numeric targets ("8 glasses") and partial progress per day.
+ALTER TABLE task ADD COLUMN target_amount REAL;
+ALTER TABLE task ADD COLUMN target_unit TEXT;
+
+-- status 0 marks a day with progress logged but the target not yet reached.
+-- SQLite can't alter a CHECK constraint, so the table is rebuilt.
+CREATE TABLE task_completion_new (
+    task_id   INTEGER NOT NULL,
+    day       INTEGER NOT NULL,
+    status    INTEGER NOT NULL CHECK(status IN (0,1,2)),
+    done_at   INTEGER,
+    value     REAL,
+    PRIMARY KEY (task_id, day),
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+
+INSERT INTO task_completion_new (task_id, day, status, done_at)
+    SELECT task_id, day, status, done_at FROM task_completion;
+
+DROP TABLE task_completion;
+ALTER TABLE task_completion_new RENAME TO task_completion;
+
+CREATE INDEX IF NOT EXISTS idx_completion_day ON task_completion(day);
@@ -0,0 +1,27 @@
+-- Drops the CHECK on task_schedule.type so new schedule types don't need a
+-- migration, Recurrence::parse already rejects unknown ones. Monthly
+-- schedules may now fall on days 29-31, -1 stands for the last day.
+-- SQLite can't alter a CHECK constraint, so the table is rebuilt.
+CREATE TABLE task_schedule_new (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id         INTEGER NOT NULL,
+    effective_from  INTEGER NOT NULL,
+    effective_to    INTEGER,
+    type            TEXT NOT NULL,
+    weekday_mask    INTEGER,
+    monthday        INTEGER CHECK(monthday = -1 OR monthday BETWEEN 1 AND 31),
+    interval_days   INTEGER,
+    params_json     TEXT,
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+
+INSERT INTO task_schedule_new
+    (id, task_id, effective_from, effective_to, type, weekday_mask, monthday, interval_days, params_json)
+    SELECT id, task_id, effective_from, effective_to, type, weekday_mask, monthday, interval_days, params_json
+    FROM task_schedule;
+
+DROP TABLE task_schedule;
+ALTER TABLE task_schedule_new RENAME TO task_schedule;
+
+CREATE INDEX IF NOT EXISTS idx_schedule_task_effective
+    ON task_schedule(task_id, effective_from, effective_to);
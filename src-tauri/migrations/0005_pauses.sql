@@ -0,0 +1,14 @@
+-- Vacation and per-task pauses. Nothing is due on a paused day and streaks
+-- skip over it. A NULL task_id pauses every task, a NULL end_day runs until
+-- the pause is ended.
+CREATE TABLE pause (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id     INTEGER,
+    start_day   INTEGER NOT NULL,
+    end_day     INTEGER,
+    created_at  INTEGER NOT NULL DEFAULT (unixepoch()),
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE,
+    CHECK(end_day IS NULL OR end_day >= start_day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_pause_task ON pause(task_id);
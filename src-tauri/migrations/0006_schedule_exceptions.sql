@@ -0,0 +1,10 @@
+-- Single occurrences moved, cancelled or added without touching the rule.
+-- A move is stored as a cancel on the old day and an add on the new one.
+CREATE TABLE schedule_exception (
+    task_id     INTEGER NOT NULL,
+    day         INTEGER NOT NULL,
+    kind        TEXT NOT NULL CHECK(kind IN ('cancel','add')),
+    created_at  INTEGER NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (task_id, day),
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
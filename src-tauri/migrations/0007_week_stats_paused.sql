@@ -0,0 +1,6 @@
+-- week_stats caches the weekly streak per finished week. A week with
+-- nothing due because of a pause neither counts nor breaks the streak.
+ALTER TABLE week_stats ADD COLUMN paused INTEGER NOT NULL DEFAULT 0;
+
+-- Nothing wrote the table before, start it clean
+DELETE FROM week_stats;
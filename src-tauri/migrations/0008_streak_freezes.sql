@@ -0,0 +1,14 @@
+-- Streak freezes: earned for keeping a streak going, spent automatically on
+-- a missed due day so the streak survives it. Both are derived from the
+-- completion history whenever a task's stats are recomputed.
+ALTER TABLE task_stats ADD COLUMN freezes INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE streak_freeze (
+    task_id     INTEGER NOT NULL,
+    day         INTEGER NOT NULL,
+    created_at  INTEGER NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (task_id, day),
+    FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_streak_freeze_day ON streak_freeze(day);
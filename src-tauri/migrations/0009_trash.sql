@@ -0,0 +1,5 @@
+-- Deleted tasks go to the trash first and keep their history until it is
+-- purged after the retention period.
+ALTER TABLE task ADD COLUMN deleted_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_task_deleted ON task(deleted_at);
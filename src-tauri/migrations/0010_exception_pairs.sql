@@ -0,0 +1,14 @@
+-- Links the two ends of a moved occurrence, so restoring either day undoes
+-- the whole move. NULL for a plain cancel or add.
+ALTER TABLE schedule_exception ADD COLUMN paired_day INTEGER;
+
+-- Moves so far wrote their cancel and add together, in the same second.
+-- Pair those up where that's the only thing the task did that second.
+UPDATE schedule_exception AS e SET paired_day = (
+    SELECT o.day FROM schedule_exception o
+    WHERE o.task_id = e.task_id AND o.created_at = e.created_at AND o.kind != e.kind
+)
+WHERE (
+    SELECT COUNT(*) FROM schedule_exception o
+    WHERE o.task_id = e.task_id AND o.created_at = e.created_at
+) = 2;
@@ -0,0 +1,147 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+/// Maps instants to day indices: the number of days between 1970-01-01 and
+/// the calendar date the instant falls on in the configured zone. A day runs
+/// from `day_start_hour` local time to the same hour the next morning, so
+/// with a start hour of 4 anything before 04:00 still counts as yesterday.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DayClock {
+    tz: Tz,
+    day_start_hour: u32,
+}
+
+impl DayClock {
+    pub fn new(tz: Tz, day_start_hour: u32) -> DayClock {
+        DayClock { tz, day_start_hour }
+    }
+
+    /// Clock used before day indices were zone aware.
+    pub fn utc() -> DayClock {
+        DayClock::new(Tz::UTC, 0)
+    }
+
+    /// `timezone` is an IANA name, `None` picks up the system zone and falls
+    /// back to UTC if that can't be determined.
+    pub fn from_setting(timezone: Option<&str>, day_start_hour: u32) -> Result<DayClock, String> {
+        if day_start_hour > 23 {
+            return Err(format!(
+                "day start hour must be between 0 and 23, got {}",
+                day_start_hour
+            ));
+        }
+        let tz = match timezone {
+            Some(name) => name
+                .parse::<Tz>()
+                .map_err(|_| format!("unknown timezone {:?}", name))?,
+            None => iana_time_zone::get_timezone()
+                .ok()
+                .and_then(|name| name.parse::<Tz>().ok())
+                .unwrap_or(Tz::UTC),
+        };
+        Ok(DayClock::new(tz, day_start_hour))
+    }
+
+    /// Stable identifier, stored alongside the data so a zone or rollover
+    /// change can be detected. Looks like `Europe/Berlin` or `Europe/Berlin@4`.
+    pub fn id(&self) -> String {
+        match self.day_start_hour {
+            0 => self.tz.name().to_string(),
+            hour => format!("{}@{}", self.tz.name(), hour),
+        }
+    }
+
+    pub fn parse_id(id: &str) -> Option<DayClock> {
+        let (tz, hour) = match id.split_once('@') {
+            Some((tz, hour)) => (tz, hour.parse().ok()?),
+            None => (id, 0),
+        };
+        Some(DayClock::new(tz.parse().ok()?, hour))
+    }
+
+    pub fn day_of(&self, at: DateTime<Utc>) -> i64 {
+        let local = at.with_timezone(&self.tz).naive_local();
+        let shifted = local - chrono::Duration::hours(self.day_start_hour as i64);
+        date_to_day(shifted.date())
+    }
+
+    /// Same as `day_of` for a unix timestamp in seconds.
+    pub fn day_of_timestamp(&self, ts: i64) -> i64 {
+        self.day_of(DateTime::from_timestamp(ts, 0).unwrap_or_default())
+    }
+}
+
+pub fn day_to_date(day: i64) -> NaiveDate {
+    DateTime::UNIX_EPOCH.date_naive() + chrono::Duration::days(day)
+}
+
+pub fn date_to_day(date: NaiveDate) -> i64 {
+    (date - DateTime::UNIX_EPOCH.date_naive()).num_days()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(at: &str) -> DateTime<Utc> {
+        chrono::NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn date(day: i64) -> String {
+        day_to_date(day).to_string()
+    }
+
+    #[test]
+    fn the_day_starts_at_the_start_hour() {
+        let clock = DayClock::from_setting(Some("America/New_York"), 3).unwrap();
+        // 02:59 and 03:00 in New York, which is UTC-5 in January
+        assert_eq!(date(clock.day_of(utc("2024-01-15 07:59"))), "2024-01-14");
+        assert_eq!(date(clock.day_of(utc("2024-01-15 08:00"))), "2024-01-15");
+        // Midnight is just another hour of the day before
+        assert_eq!(date(clock.day_of(utc("2024-01-15 05:00"))), "2024-01-14");
+    }
+
+    #[test]
+    fn daylight_saving_moves_the_boundary_with_the_clocks() {
+        let clock = DayClock::from_setting(Some("Europe/Berlin"), 3).unwrap();
+        // Spring forward skips 02:00 to 03:00, the day ends at 01:59 CET
+        assert_eq!(date(clock.day_of(utc("2024-03-31 00:59"))), "2024-03-30");
+        assert_eq!(date(clock.day_of(utc("2024-03-31 01:00"))), "2024-03-31");
+        // Fall back runs 02:00 to 03:00 twice, both belong to the day before
+        assert_eq!(date(clock.day_of(utc("2024-10-27 00:59"))), "2024-10-26");
+        assert_eq!(date(clock.day_of(utc("2024-10-27 01:59"))), "2024-10-26");
+        assert_eq!(date(clock.day_of(utc("2024-10-27 02:00"))), "2024-10-27");
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        for clock in [
+            DayClock::utc(),
+            DayClock::from_setting(Some("Europe/Berlin"), 4).unwrap(),
+        ] {
+            assert_eq!(DayClock::parse_id(&clock.id()), Some(clock));
+        }
+        assert_eq!(DayClock::utc().id(), "UTC");
+        assert_eq!(
+            DayClock::from_setting(Some("Europe/Berlin"), 4)
+                .unwrap()
+                .id(),
+            "Europe/Berlin@4"
+        );
+        assert_eq!(DayClock::parse_id("Mars/Olympus"), None);
+    }
+
+    #[test]
+    fn bad_settings_are_refused() {
+        assert_eq!(
+            DayClock::from_setting(None, 24).unwrap_err(),
+            "day start hour must be between 0 and 23, got 24"
+        );
+        assert_eq!(
+            DayClock::from_setting(Some("Mars/Olympus"), 0).unwrap_err(),
+            "unknown timezone \"Mars/Olympus\""
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use chrono::Weekday;
+
+use crate::clock::DayClock;
+use crate::db::DbBackend;
+
+pub const SETTINGS_FILE: &str = "settings.toml";
+const LOCAL_DB_FILE: &str = "talender.db";
+const REPLICA_DB_FILE: &str = "talender-replica.db";
+
+// Environment overrides, applied on top of the settings file
+const ENV_DB_MODE: &str = "TALENDER_DB_MODE";
+const ENV_DB_URL: &str = "TURSO_DATABASE_URL";
+const ENV_DB_AUTH_TOKEN: &str = "TURSO_AUTH_TOKEN";
+const ENV_DB_PATH: &str = "TALENDER_DB_PATH";
+const ENV_SYNC_INTERVAL: &str = "TALENDER_SYNC_INTERVAL_SECS";
+const ENV_WEEK_START: &str = "TALENDER_WEEK_START";
+const ENV_TIMEZONE: &str = "TALENDER_TIMEZONE";
+const ENV_DAY_START_HOUR: &str = "TALENDER_DAY_START_HOUR";
+const ENV_TRASH_RETENTION: &str = "TALENDER_TRASH_RETENTION_DAYS";
+const ENV_PROFILE: &str = "TALENDER_PROFILE";
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendMode {
+    #[default]
+    Local,
+    Remote,
+    Replica,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WeekStart {
+    #[default]
+    Monday,
+    Sunday,
+}
+
+impl WeekStart {
+    pub fn weekday(self) -> Weekday {
+        match self {
+            WeekStart::Monday => Weekday::Mon,
+            WeekStart::Sunday => Weekday::Sun,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct DatabaseSettings {
+    pub mode: BackendMode,
+    pub url: Option<String>,
+    pub auth_token: Option<String>,
+    /// Database file for local and replica mode, defaults to the app data dir.
+    pub path: Option<PathBuf>,
+}
+
+/// Day settings for one person or device, so several of them can share a
+/// settings file. Values left out come from the top level.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    pub timezone: Option<String>,
+    pub day_start_hour: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub database: DatabaseSettings,
+    /// Background sync period in replica mode, 0 turns it off.
+    pub sync_interval_secs: u64,
+    pub week_start: WeekStart,
+    /// IANA zone name, e.g. "Europe/Berlin". `None` means the system zone.
+    pub timezone: Option<String>,
+    /// Local hour at which a new day begins, for logging late nights on the
+    /// previous day. 0 is midnight.
+    pub day_start_hour: u32,
+    /// Days a deleted task stays in the trash before it is purged, 0 keeps
+    /// it until restored.
+    pub trash_retention_days: u32,
+    /// Name of the entry in `profiles` to run with, `None` uses the top level
+    /// values as they are.
+    pub profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            database: DatabaseSettings::default(),
+            sync_interval_secs: 60,
+            week_start: WeekStart::default(),
+            timezone: None,
+            day_start_hour: 0,
+            trash_retention_days: 30,
+            profile: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "settings file: {}", e),
+            ConfigError::Parse(e) => write!(f, "settings file is not valid TOML: {}", e),
+            ConfigError::Invalid(e) => write!(f, "invalid setting: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+/// Settings as managed by tauri. `file` is what lives on disk, `effective`
+/// is the file with env overrides applied and is what the app runs on.
+pub struct ConfigState {
+    pub path: PathBuf,
+    pub file: tokio::sync::RwLock<Settings>,
+    pub effective: tokio::sync::RwLock<Settings>,
+}
+
+impl ConfigState {
+    pub fn load(path: PathBuf) -> Result<ConfigState, ConfigError> {
+        let file = Settings::load(&path)?;
+        let effective = file.clone().with_env_overrides()?;
+        effective.day_clock()?;
+        Ok(ConfigState {
+            path,
+            file: tokio::sync::RwLock::new(file),
+            effective: tokio::sync::RwLock::new(effective),
+        })
+    }
+
+    /// Persists `settings` to the settings file. Backend and sync interval
+    /// changes only apply after a restart.
+    pub async fn update(&self, settings: Settings) -> Result<(), ConfigError> {
+        let effective = settings.clone().with_env_overrides()?;
+        effective.day_clock()?;
+        settings.save(&self.path)?;
+        *self.file.write().await = settings;
+        *self.effective.write().await = effective;
+        Ok(())
+    }
+
+    pub async fn day_clock(&self) -> Result<DayClock, ConfigError> {
+        self.effective.read().await.day_clock()
+    }
+
+    pub async fn week_start(&self) -> Weekday {
+        self.effective.read().await.week_start.weekday()
+    }
+}
+
+impl Settings {
+    /// Reads the settings file, falling back to defaults when it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Settings, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| ConfigError::Parse(e.to_string()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let contents =
+            toml::to_string_pretty(self).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn with_env_overrides(self) -> Result<Settings, ConfigError> {
+        self.with_overrides(env)
+    }
+
+    /// Applies the overrides `env` returns a value for, by env var name.
+    fn with_overrides(
+        mut self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Settings, ConfigError> {
+        if let Some(profile) = env(ENV_PROFILE) {
+            self.profile = Some(profile);
+        }
+        // The profile stands in for the file's values, env vars still win
+        self = self.with_profile()?;
+        if let Some(mode) = env(ENV_DB_MODE) {
+            self.database.mode = parse_enum(ENV_DB_MODE, &mode)?;
+        }
+        if let Some(url) = env(ENV_DB_URL) {
+            self.database.url = Some(url);
+        }
+        if let Some(token) = env(ENV_DB_AUTH_TOKEN) {
+            self.database.auth_token = Some(token);
+        }
+        if let Some(path) = env(ENV_DB_PATH) {
+            self.database.path = Some(PathBuf::from(path));
+        }
+        if let Some(secs) = env(ENV_SYNC_INTERVAL) {
+            self.sync_interval_secs = secs.parse().map_err(|_| {
+                ConfigError::Invalid(format!("{} must be a number of seconds", ENV_SYNC_INTERVAL))
+            })?;
+        }
+        if let Some(week_start) = env(ENV_WEEK_START) {
+            self.week_start = parse_enum(ENV_WEEK_START, &week_start)?;
+        }
+        if let Some(tz) = env(ENV_TIMEZONE) {
+            self.timezone = Some(tz);
+        }
+        if let Some(hour) = env(ENV_DAY_START_HOUR) {
+            self.day_start_hour = hour.parse().map_err(|_| {
+                ConfigError::Invalid(format!(
+                    "{} must be an hour from 0 to 23",
+                    ENV_DAY_START_HOUR
+                ))
+            })?;
+        }
+        if let Some(days) = env(ENV_TRASH_RETENTION) {
+            self.trash_retention_days = days.parse().map_err(|_| {
+                ConfigError::Invalid(format!("{} must be a number of days", ENV_TRASH_RETENTION))
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// Copies the selected profile's values over the top level ones.
+    fn with_profile(mut self) -> Result<Settings, ConfigError> {
+        let Some(name) = &self.profile else {
+            return Ok(self);
+        };
+        let profile = self.profiles.get(name).cloned().ok_or_else(|| {
+            ConfigError::Invalid(format!("profile {:?} is not in [profiles]", name))
+        })?;
+        if let Some(tz) = profile.timezone {
+            self.timezone = Some(tz);
+        }
+        if let Some(hour) = profile.day_start_hour {
+            self.day_start_hour = hour;
+        }
+        Ok(self)
+    }
+
+    pub fn day_clock(&self) -> Result<DayClock, ConfigError> {
+        DayClock::from_setting(self.timezone.as_deref(), self.day_start_hour)
+            .map_err(ConfigError::Invalid)
+    }
+
+    /// Resolves the database settings into something `db::init_db` can open.
+    pub fn backend(&self, data_dir: &Path) -> Result<DbBackend, ConfigError> {
+        let db = &self.database;
+        let path = |default: &str| db.path.clone().unwrap_or_else(|| data_dir.join(default));
+        let remote = || -> Result<(String, String), ConfigError> {
+            let url = db.url.clone().ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "database.url (or {}) is required in {:?} mode",
+                    ENV_DB_URL, db.mode
+                ))
+            })?;
+            Ok((url, db.auth_token.clone().unwrap_or_default()))
+        };
+
+        Ok(match db.mode {
+            BackendMode::Local => DbBackend::Local {
+                path: path(LOCAL_DB_FILE),
+            },
+            BackendMode::Remote => {
+                let (url, auth_token) = remote()?;
+                DbBackend::Remote { url, auth_token }
+            }
+            BackendMode::Replica => {
+                let (url, auth_token) = remote()?;
+                DbBackend::Replica {
+                    path: path(REPLICA_DB_FILE),
+                    url,
+                    auth_token,
+                }
+            }
+        })
+    }
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+fn parse_enum<T: serde::de::DeserializeOwned>(key: &str, value: &str) -> Result<T, ConfigError> {
+    serde_json::from_value(serde_json::Value::String(value.to_lowercase()))
+        .map_err(|_| ConfigError::Invalid(format!("{}={} is not a recognised value", key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Settings {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn overridden(vars: &[(&str, &str)]) -> Result<Settings, ConfigError> {
+        apply(Settings::default(), vars)
+    }
+
+    fn apply(settings: Settings, vars: &[(&str, &str)]) -> Result<Settings, ConfigError> {
+        settings.with_overrides(|key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    #[test]
+    fn defaults() {
+        let s = parse("");
+        assert_eq!(s.database.mode, BackendMode::Local);
+        assert_eq!(s.database.url, None);
+        assert_eq!(s.sync_interval_secs, 60);
+        assert_eq!(s.week_start, WeekStart::Monday);
+        assert_eq!(s.timezone, None);
+        assert_eq!(s.day_start_hour, 0);
+        assert_eq!(s.trash_retention_days, 30);
+        assert_eq!(s.profile, None);
+        assert!(s.profiles.is_empty());
+        let dir = Path::new("/data");
+        assert!(matches!(
+            s.backend(dir).unwrap(),
+            DbBackend::Local { path } if path == dir.join(LOCAL_DB_FILE)
+        ));
+    }
+
+    #[test]
+    fn reads_the_file() {
+        let s = parse(
+            r#"
+            sync_interval_secs = 15
+            week_start = "sunday"
+            timezone = "Europe/Berlin"
+            day_start_hour = 4
+
+            [database]
+            mode = "replica"
+            url = "libsql://example.turso.io"
+            auth_token = "secret"
+            "#,
+        );
+        assert_eq!(s.sync_interval_secs, 15);
+        assert_eq!(s.week_start.weekday(), Weekday::Sun);
+        assert_eq!(s.day_start_hour, 4);
+        // Left out, so still the default
+        assert_eq!(s.trash_retention_days, 30);
+        assert!(s.day_clock().is_ok());
+        match s.backend(Path::new("/data")).unwrap() {
+            DbBackend::Replica {
+                path,
+                url,
+                auth_token,
+            } => {
+                assert_eq!(path, Path::new("/data").join(REPLICA_DB_FILE));
+                assert_eq!(url, "libsql://example.turso.io");
+                assert_eq!(auth_token, "secret");
+            }
+            _ => panic!("expected a replica"),
+        }
+    }
+
+    #[test]
+    fn unknown_values_are_refused() {
+        assert!(toml::from_str::<Settings>("week_start = \"friday\"").is_err());
+        assert!(toml::from_str::<Settings>("[database]\nmode = \"cloud\"").is_err());
+        let s = parse("[database]\nmode = \"remote\"");
+        // No Debug on DbBackend, it carries the auth token
+        let Err(e) = s.backend(Path::new("/data")) else {
+            panic!("remote mode without a url");
+        };
+        assert_eq!(
+            e.to_string(),
+            "invalid setting: database.url (or TURSO_DATABASE_URL) is required in Remote mode"
+        );
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let s = overridden(&[
+            (ENV_DB_MODE, "Remote"),
+            (ENV_DB_URL, "http://127.0.0.1:8080"),
+            (ENV_SYNC_INTERVAL, "0"),
+            (ENV_WEEK_START, "SUNDAY"),
+            (ENV_TIMEZONE, "America/New_York"),
+            (ENV_DAY_START_HOUR, "3"),
+            (ENV_TRASH_RETENTION, "0"),
+        ])
+        .unwrap();
+        assert_eq!(s.database.mode, BackendMode::Remote);
+        assert_eq!(s.database.url.as_deref(), Some("http://127.0.0.1:8080"));
+        assert_eq!(s.sync_interval_secs, 0);
+        assert_eq!(s.week_start, WeekStart::Sunday);
+        assert_eq!(s.timezone.as_deref(), Some("America/New_York"));
+        assert_eq!(s.day_start_hour, 3);
+        assert_eq!(s.trash_retention_days, 0);
+        // Nothing set leaves the settings alone
+        assert_eq!(overridden(&[]).unwrap().sync_interval_secs, 60);
+    }
+
+    #[test]
+    fn profiles() {
+        let file = parse(
+            r#"
+            timezone = "Europe/Berlin"
+            day_start_hour = 0
+            profile = "night"
+
+            [profiles.night]
+            day_start_hour = 4
+
+            [profiles.travel]
+            timezone = "Asia/Tokyo"
+            "#,
+        );
+        let day = |s: &Settings| (s.timezone.clone().unwrap(), s.day_start_hour);
+        let with = |vars| apply(file.clone(), vars).unwrap();
+        assert_eq!(day(&with(&[])), ("Europe/Berlin".to_string(), 4));
+        assert_eq!(
+            day(&with(&[(ENV_PROFILE, "travel")])),
+            ("Asia/Tokyo".to_string(), 0)
+        );
+        // Env overrides beat the profile
+        assert_eq!(
+            day(&with(&[(ENV_DAY_START_HOUR, "2")])),
+            ("Europe/Berlin".to_string(), 2)
+        );
+        // What's on disk keeps its own values
+        assert_eq!(day(&file), ("Europe/Berlin".to_string(), 0));
+        let saved = parse(&toml::to_string_pretty(&file).unwrap());
+        assert_eq!(saved.profile.as_deref(), Some("night"));
+        assert_eq!(saved.profiles, file.profiles);
+
+        let unknown = apply(parse("profile = \"work\""), &[]);
+        assert_eq!(
+            unknown.unwrap_err().to_string(),
+            "invalid setting: profile \"work\" is not in [profiles]"
+        );
+    }
+
+    #[test]
+    fn bad_env_values_are_named() {
+        let error = |vars| overridden(vars).unwrap_err().to_string();
+        assert_eq!(
+            error(&[(ENV_WEEK_START, "friday")]),
+            "invalid setting: TALENDER_WEEK_START=friday is not a recognised value"
+        );
+        assert_eq!(
+            error(&[(ENV_SYNC_INTERVAL, "soon")]),
+            "invalid setting: TALENDER_SYNC_INTERVAL_SECS must be a number of seconds"
+        );
+        assert_eq!(
+            error(&[(ENV_DAY_START_HOUR, "-1")]),
+            "invalid setting: TALENDER_DAY_START_HOUR must be an hour from 0 to 23"
+        );
+    }
+}
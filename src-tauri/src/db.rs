@@ -1,11 +1,75 @@
 use chrono::prelude::*;
-use libsql::{Builder, Connection, Result};
+use libsql::{Builder, Connection, Database};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use crate::clock::{self, DayClock};
+use crate::recurrence::{Exception, History, Recurrence, ScheduleVersion};
+
+#[derive(Debug)]
+pub enum DbError {
+    Db(libsql::Error),
+    /// The request itself can't be carried out, e.g. a schedule that doesn't
+    /// parse. The message is meant for the user.
+    Invalid(String),
+    /// An offline replica whose local copy is older than this build's schema.
+    /// Migrating needs the primary, so nothing works until a sync succeeds.
+    SyncRequired {
+        local: i64,
+        latest: i64,
+    },
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Db(e) => write!(f, "database: {}", e),
+            DbError::Invalid(e) => f.write_str(e),
+            DbError::SyncRequired { local, latest } => write!(
+                f,
+                "sync required: the local copy is at schema version {} but this version of talender needs {}, connect to the server and sync",
+                local, latest
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<libsql::Error> for DbError {
+    fn from(e: libsql::Error) -> Self {
+        DbError::Db(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DbError>;
 
 pub struct AppState {
     pub db: tokio::sync::Mutex<Connection>,
+    /// Kept around for `sync()`, which lives on the database rather than the connection.
+    pub database: Database,
+    /// Set while an offline replica waits for the sync that lets it migrate,
+    /// see `DbError::SyncRequired`.
+    pub sync_required: std::sync::Mutex<Option<String>>,
+    /// Undo and redo for the commands of this session.
+    pub journal: tokio::sync::Mutex<crate::journal::Journal>,
+}
+
+impl AppState {
+    /// The connection, for commands. Refused while a sync is required.
+    pub async fn conn(
+        &self,
+    ) -> std::result::Result<tokio::sync::MutexGuard<'_, Connection>, String> {
+        if let Some(e) = self.sync_required.lock().unwrap().clone() {
+            return Err(e);
+        }
+        Ok(self.db.lock().await)
+    }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
 pub struct Task {
     pub id: i64,
     pub title: String,
@@ -13,93 +77,217 @@ pub struct Task {
     pub is_active: bool,
     pub created_at: i64,
     pub archived_at: Option<i64>,
+    /// Amount needed for a day to count as done, `None` for plain yes/no habits.
+    pub target_amount: Option<f64>,
+    pub target_unit: Option<String>,
+    /// Set while the task is in the trash.
+    pub deleted_at: Option<i64>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
 pub struct TaskSchedule {
     pub id: i64,
     pub task_id: i64,
     pub effective_from: i64,
     pub effective_to: Option<i64>,
     #[serde(rename = "type")]
-    pub type_: String, // "daily", "weekly", "monthly", "custom"
+    pub type_: String, // "daily", "weekly", "monthly", "monthly_nth", "yearly", "quota", "once", "custom", "rrule"
     pub weekday_mask: Option<i64>,
     pub monthday: Option<i64>,
     pub interval_days: Option<i64>,
     pub params_json: Option<String>,
 }
 
-pub async fn init_db(url: &str, token: &str) -> Result<Connection> {
-    let db = Builder::new_remote(url.to_string(), token.to_string())
-        .build()
-        .await
-        .expect("Failed to build db");
+impl TaskSchedule {
+    /// Reads the `task_schedule` columns, in table order, starting at column
+    /// `offset`.
+    pub fn from_row(row: &libsql::Row, offset: i32) -> libsql::Result<TaskSchedule> {
+        Ok(TaskSchedule {
+            id: row.get(offset)?,
+            task_id: row.get(offset + 1)?,
+            effective_from: row.get(offset + 2)?,
+            effective_to: row.get(offset + 3)?,
+            type_: row.get(offset + 4)?,
+            weekday_mask: row.get(offset + 5)?,
+            monthday: row.get(offset + 6)?,
+            interval_days: row.get(offset + 7)?,
+            params_json: row.get(offset + 8)?,
+        })
+    }
+}
+
+/// Rejects schedules that can't be evaluated before they reach the database.
+fn validate_schedule(
+    frequency_type: &str,
+    weekday_mask: Option<i64>,
+    monthday: Option<i64>,
+    interval_days: Option<i64>,
+    params_json: Option<&str>,
+    effective_from: i64,
+) -> Result<()> {
+    Recurrence::parse(
+        frequency_type,
+        weekday_mask,
+        monthday,
+        interval_days,
+        params_json,
+        effective_from,
+    )
+    .and_then(|recurrence| recurrence.validate())
+    .map_err(DbError::Invalid)
+}
+
+/// Where the database lives.
+pub enum DbBackend {
+    /// Turso / libsql server, every query is a network round trip.
+    Remote { url: String, auth_token: String },
+    /// Plain libsql file on disk, works without network access.
+    Local { path: PathBuf },
+    /// Embedded replica: reads hit the local file, writes go to the remote
+    /// primary and come back down on the next sync.
+    Replica {
+        path: PathBuf,
+        url: String,
+        auth_token: String,
+    },
+}
+
+pub async fn init_db(backend: &DbBackend) -> Result<(Database, Connection)> {
+    let db = match backend {
+        DbBackend::Remote { url, auth_token } => {
+            Builder::new_remote(url.clone(), auth_token.clone())
+                .build()
+                .await
+        }
+        DbBackend::Local { path } => Builder::new_local(path).build().await,
+        DbBackend::Replica {
+            path,
+            url,
+            auth_token,
+        } => {
+            Builder::new_remote_replica(path, url.clone(), auth_token.clone())
+                .build()
+                .await
+        }
+    }
+    .expect("Failed to build db");
+
+    let mut online = true;
+    if let DbBackend::Replica { .. } = backend {
+        // Pull the primary down before touching the schema. Offline the app
+        // still starts on the local copy, the periodic sync catches up later.
+        if let Err(e) = db.sync().await {
+            eprintln!(
+                "initial sync failed, starting from the local replica: {}",
+                e
+            );
+            online = false;
+        }
+    }
+
     let conn = db.connect().expect("Failed to connect to db");
-    // Schema
-    conn.execute_batch(
-        "
-        CREATE TABLE IF NOT EXISTS task (
-            id            INTEGER PRIMARY KEY AUTOINCREMENT,
-            title         TEXT NOT NULL,
-            notes         TEXT,
-            is_active     INTEGER NOT NULL DEFAULT 1,
-            created_at    INTEGER NOT NULL DEFAULT (unixepoch()),
-            archived_at   INTEGER
-        );
 
-        CREATE TABLE IF NOT EXISTS task_schedule (
-            id              INTEGER PRIMARY KEY AUTOINCREMENT,
-            task_id         INTEGER NOT NULL,
-            effective_from  INTEGER NOT NULL,
-            effective_to    INTEGER,
-            type            TEXT NOT NULL CHECK(type IN ('daily','weekly','monthly','custom')),
-            weekday_mask    INTEGER,
-            monthday        INTEGER CHECK(monthday BETWEEN 1 AND 28),
-            interval_days   INTEGER,
-            params_json     TEXT,
-            FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
-        );
+    if let DbBackend::Local { .. } = backend {
+        // SQLite ships with foreign keys off, the schema relies on cascading deletes
+        conn.execute("PRAGMA foreign_keys = ON", ()).await?;
+    }
 
-        CREATE INDEX IF NOT EXISTS idx_schedule_task_effective
-            ON task_schedule(task_id, effective_from, effective_to);
+    // Writes can't reach the primary while offline, the local copy is left
+    // as it is and `check_schema` tells whether it can be used
+    if online {
+        crate::migrations::run(&conn).await?;
+    }
 
-        CREATE TABLE IF NOT EXISTS task_completion (
-            task_id   INTEGER NOT NULL,
-            day       INTEGER NOT NULL,
-            status    INTEGER NOT NULL CHECK(status IN (1,2)),
-            done_at   INTEGER,
-            PRIMARY KEY (task_id, day),
-            FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
-        );
+    if let (DbBackend::Replica { .. }, true) = (backend, online) {
+        // Schema writes went to the primary, bring them back locally
+        if let Err(e) = db.sync().await {
+            eprintln!("sync after migrating failed: {}", e);
+        }
+    }
 
-        CREATE INDEX IF NOT EXISTS idx_completion_day ON task_completion(day);
+    Ok((db, conn))
+}
 
-        CREATE TABLE IF NOT EXISTS task_stats (
-            task_id            INTEGER PRIMARY KEY,
-            current_streak     INTEGER NOT NULL DEFAULT 0,
-            best_streak        INTEGER NOT NULL DEFAULT 0,
-            last_completed_day INTEGER,
-            updated_at         INTEGER NOT NULL DEFAULT (unixepoch()),
-            FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
-        );
+/// A fresh in-memory database migrated to the latest schema, for tests.
+#[cfg(test)]
+pub async fn open_test_db() -> (Database, Connection) {
+    let path = PathBuf::from(":memory:");
+    init_db(&DbBackend::Local { path }).await.unwrap()
+}
 
-        CREATE TABLE IF NOT EXISTS week_stats (
-            week_start_day  INTEGER PRIMARY KEY,
-            all_done        INTEGER NOT NULL DEFAULT 0,
-            due_count       INTEGER NOT NULL DEFAULT 0,
-            done_count      INTEGER NOT NULL DEFAULT 0,
-            updated_at      INTEGER NOT NULL DEFAULT (unixepoch())
-        );
-        ",
-    )
-    .await?;
+/// Fails with `DbError::SyncRequired` when the schema is behind this build,
+/// which only an offline replica can leave it.
+pub async fn check_schema(conn: &Connection) -> Result<()> {
+    // A replica that never synced has no tables at all
+    let local = crate::migrations::current_version(conn).await.unwrap_or(0);
+    if local < crate::migrations::LATEST_VERSION {
+        return Err(DbError::SyncRequired {
+            local,
+            latest: crate::migrations::LATEST_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Brings stored data in line with the settings, once the schema is current
+/// and before the first command runs.
+pub async fn prepare(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    trash_retention_days: u32,
+) -> Result<()> {
+    migrate_day_clock(conn, clock, week_start).await?;
+    recompute_stale_stats(conn, clock, week_start).await?;
+    purge_trash(conn, trash_retention_days).await
+}
+
+pub fn get_day_index(clock: &DayClock) -> i64 {
+    clock.day_of(Utc::now())
+}
+
+/// State of a task on one day. Stored in `task_completion.status` as 0 (some
+/// progress, target not reached), 1 (done) and 2 (skipped). Cleared days have
+/// no row at all.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionStatus {
+    Cleared,
+    InProgress,
+    Done,
+    Skipped,
+}
+
+impl CompletionStatus {
+    pub fn from_db(status: Option<i64>) -> CompletionStatus {
+        match status {
+            Some(0) => CompletionStatus::InProgress,
+            Some(1) => CompletionStatus::Done,
+            Some(2) => CompletionStatus::Skipped,
+            _ => CompletionStatus::Cleared,
+        }
+    }
+
+    fn to_db(self) -> Option<i64> {
+        match self {
+            CompletionStatus::Cleared => None,
+            CompletionStatus::InProgress => Some(0),
+            CompletionStatus::Done => Some(1),
+            CompletionStatus::Skipped => Some(2),
+        }
+    }
 
-    Ok(conn)
+    /// Done or skipped, either way nothing is left to do that day.
+    pub fn is_satisfied(self) -> bool {
+        matches!(self, CompletionStatus::Done | CompletionStatus::Skipped)
+    }
 }
 
-pub fn get_day_index() -> i64 {
-    let now = Utc::now();
-    now.timestamp() / 86400
+/// One `task_completion` row.
+#[derive(Clone, Copy, Debug)]
+struct Completion {
+    status: CompletionStatus,
+    value: Option<f64>,
 }
 
 #[derive(serde::Serialize)]
@@ -109,6 +297,24 @@ pub struct TaskWithStats {
     pub current_streak: i64,
     pub best_streak: i64,
     pub today_status: bool, // true if done today
+    pub status: CompletionStatus,
+    pub progress: Option<f64>, // amount logged today, for tasks with a target
+    pub is_due: bool,
+    pub next_due: Option<i64>, // first due day after the listed one
+    pub quota: Option<QuotaProgress>,
+    pub overdue: bool, // one-off past its deadline
+    pub paused: bool,
+    pub freezes: i64, // streak freezes left to cover a missed day
+}
+
+/// Where a "quota" task stands in the period around the listed day, e.g.
+/// 2 of 3 this week.
+#[derive(serde::Serialize)]
+pub struct QuotaProgress {
+    pub done: i64,
+    pub times: i64,
+    pub period_start: i64,
+    pub period_end: i64,
 }
 
 #[derive(serde::Serialize)]
@@ -116,45 +322,76 @@ pub struct MonthTask {
     pub id: i64,
     pub title: String,
     pub is_done: bool,
+    pub status: CompletionStatus,
+    pub progress: Option<f64>,
+    pub target: Option<f64>,
+    pub frozen: bool, // missed, but a streak freeze covered it
 }
 
 #[derive(serde::Serialize)]
 pub struct MonthViewDay {
     pub day: i64,
+    pub paused: bool,      // a global pause covers the day
+    pub paused_count: i64, // tasks that would be due but are on pause
     pub due_count: i64,
     pub done_count: i64,
-    pub all_done: bool,
+    pub skipped_count: i64,
+    pub all_done: bool, // every due task done or skipped
+    pub progress: f64,  // 0..=1 across due tasks, partial amounts count pro rata
     pub tasks: Vec<MonthTask>,
 }
 
+/// A stretch of days nothing is due on, for one task or all of them.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Pause {
+    pub id: i64,
+    pub task_id: Option<i64>, // None pauses every task
+    pub start_day: i64,
+    pub end_day: Option<i64>, // inclusive, None until it is ended
+}
+
+impl Pause {
+    fn days(&self) -> RangeInclusive<i64> {
+        self.start_day..=self.end_day.unwrap_or(i64::MAX)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn add_task(
     conn: &Connection,
+    clock: &DayClock,
     title: String,
     frequency_type: String,
     weekday_mask: Option<i64>,
     monthday: Option<i64>,
     interval_days: Option<i64>,
-) -> Result<()> {
-    let day_index = get_day_index();
+    params_json: Option<String>,
+    target_amount: Option<f64>,
+    target_unit: Option<String>,
+) -> Result<i64> {
+    let day_index = get_day_index(clock);
     let created_at = Utc::now().timestamp();
-
-    conn.execute(
-        "INSERT INTO task (title, created_at) VALUES (?, ?)",
-        (title, created_at),
+    validate_schedule(
+        &frequency_type,
+        weekday_mask,
+        monthday,
+        interval_days,
+        params_json.as_deref(),
+        day_index,
+    )?;
+
+    // All or nothing, so a dropped connection can't leave a task without a schedule
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "INSERT INTO task (title, created_at, target_amount, target_unit) VALUES (?, ?, ?, ?)",
+        (title, created_at, target_amount, target_unit),
     )
     .await?;
+    let task_id = tx.last_insert_rowid();
 
-    let mut rows = conn.query("SELECT last_insert_rowid()", ()).await?;
-    let task_id: i64 = if let Some(row) = rows.next().await? {
-        row.get(0)?
-    } else {
-        0
-    };
-
-    // Use positional parameters and handle NULLs properly
-    conn.execute(
-        "INSERT INTO task_schedule (task_id, effective_from, type, weekday_mask, monthday, interval_days)
-            VALUES (?, ?, ?, ?, ?, ?)",
+    tx.execute(
+        "INSERT INTO task_schedule (task_id, effective_from, type, weekday_mask, monthday, interval_days, params_json)
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
         (
             task_id,
             day_index,
@@ -162,37 +399,45 @@ pub async fn add_task(
             weekday_mask,
             monthday,
             interval_days,
+            params_json,
         ),
     ).await?;
 
-    conn.execute(
+    tx.execute(
         "INSERT INTO task_stats (task_id, current_streak, best_streak) VALUES (?, 0, 0)",
         [task_id],
     )
     .await?;
+    tx.commit().await?;
 
-    Ok(())
+    Ok(task_id)
 }
 
-pub async fn list_tasks(conn: &Connection, day: Option<i64>) -> Result<Vec<TaskWithStats>> {
-    let target_day = day.unwrap_or_else(get_day_index);
+pub async fn list_tasks(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    day: Option<i64>,
+) -> Result<Vec<TaskWithStats>> {
+    let target_day = day.unwrap_or_else(|| get_day_index(clock));
 
     let mut rows = conn.query(
         "SELECT 
-            t.id, t.title, t.notes, t.is_active, t.created_at, t.archived_at,
-            s.id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, s.interval_days, s.params_json,
+            t.id, t.title, t.notes, t.is_active, t.created_at, t.archived_at, t.target_amount, t.target_unit,
+            s.id, s.task_id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, s.interval_days, s.params_json,
             st.current_streak, st.best_streak,
-            tc.status
+            tc.status, tc.value, st.freezes, t.deleted_at
             FROM task t
             JOIN task_schedule s ON t.id = s.task_id
             LEFT JOIN task_stats st ON t.id = st.task_id
             LEFT JOIN task_completion tc ON t.id = tc.task_id AND tc.day = ?
-            WHERE t.archived_at IS NULL AND s.effective_to IS NULL
+            WHERE t.archived_at IS NULL AND t.deleted_at IS NULL AND s.effective_to IS NULL
             ORDER BY t.created_at DESC",
             [target_day],
     ).await?;
 
     let mut tasks = Vec::new();
+    let mut versions = Vec::new();
 
     while let Some(row) = rows.next().await? {
         let task = Task {
@@ -202,478 +447,2782 @@ pub async fn list_tasks(conn: &Connection, day: Option<i64>) -> Result<Vec<TaskW
             is_active: row.get(3)?,
             created_at: row.get(4)?,
             archived_at: row.get(5)?,
+            target_amount: row.get(6)?,
+            target_unit: row.get(7)?,
+            deleted_at: row.get(22)?,
         };
-        let schedule = TaskSchedule {
-            id: row.get(6)?,
-            task_id: task.id,
-            effective_from: row.get(7)?,
-            effective_to: row.get(8)?,
-            type_: row.get(9)?,
-            weekday_mask: row.get(10)?,
-            monthday: row.get(11)?,
-            interval_days: row.get(12)?,
-            params_json: row.get(13)?,
-        };
-        let current_streak: i64 = row.get(14).unwrap_or(0);
-        let best_streak: i64 = row.get(15).unwrap_or(0);
-        let status: Option<i64> = row.get(16)?;
+        let schedule = TaskSchedule::from_row(&row, 8)?;
+        let current_streak: i64 = row.get(17).unwrap_or(0);
+        let best_streak: i64 = row.get(18).unwrap_or(0);
+        let status = CompletionStatus::from_db(row.get(19)?);
+        versions.push(ScheduleVersion::try_from(&schedule).ok());
 
         tasks.push(TaskWithStats {
             task,
             schedule,
             current_streak,
             best_streak,
-            today_status: status.is_some(),
+            today_status: status == CompletionStatus::Done,
+            status,
+            progress: row.get(20)?,
+            is_due: false,
+            next_due: None,
+            quota: None,
+            overdue: false,
+            paused: false,
+            freezes: row.get(21).unwrap_or(0),
         });
     }
 
+    let today = get_day_index(clock);
+    let pauses = load_pauses(conn).await?;
+    let mut finished = Vec::new();
+    for (task, version) in tasks.iter_mut().zip(versions) {
+        let Some(version) = version else {
+            continue;
+        };
+        let version = prepare_version(conn, version, today, &pauses).await?;
+        task.paused = version.is_paused(target_day);
+        task.is_due = version.is_due(target_day);
+        task.next_due = version.next_after(target_day);
+        task.overdue = version.is_overdue(today);
+        if version.completed_on().is_some_and(|day| day <= target_day) {
+            finished.push(task.task.id);
+        }
+        if let Some((times, period)) = version.recurrence.quota() {
+            let period = period.containing(target_day, week_start);
+            task.quota = Some(QuotaProgress {
+                done: count_done(conn, task.task.id, &period).await?,
+                times,
+                period_start: *period.start(),
+                period_end: *period.end(),
+            });
+        }
+    }
+    // One-offs leave the list once they are done
+    tasks.retain(|t| !finished.contains(&t.task.id));
+
     Ok(tasks)
 }
 
-pub async fn delete_task(conn: &Connection, task_id: i64) -> Result<()> {
-    conn.execute("DELETE FROM task WHERE id = ?", [task_id])
-        .await?;
+/// Moves a task to the trash. Its history stays until the trash is purged,
+/// `restore_from_trash` brings it back as it was.
+pub async fn delete_task(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+) -> Result<()> {
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "UPDATE task SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+        (Utc::now().timestamp(), task_id),
+    )
+    .await?;
+    // Its past due days drop out of every week it had
+    refresh_task_weeks(&tx, clock, week_start, Some(task_id)).await?;
+    tx.commit().await?;
     Ok(())
 }
 
-pub async fn delete_all_tasks(conn: &Connection) -> Result<()> {
-    conn.execute("DELETE FROM task", ()).await?;
+/// What `delete_all_tasks` wants as `confirmation`, so a stray call can't
+/// wipe the list.
+pub const DELETE_ALL_CONFIRMATION: &str = "delete all tasks";
+
+/// Moves every task to the trash.
+pub async fn delete_all_tasks(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    confirmation: &str,
+) -> Result<()> {
+    if confirmation != DELETE_ALL_CONFIRMATION {
+        return Err(DbError::Invalid(format!(
+            "deleting all tasks needs the confirmation \"{}\"",
+            DELETE_ALL_CONFIRMATION
+        )));
+    }
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "UPDATE task SET deleted_at = ? WHERE deleted_at IS NULL",
+        [Utc::now().timestamp()],
+    )
+    .await?;
+    refresh_task_weeks(&tx, clock, week_start, None).await?;
+    tx.commit().await?;
     Ok(())
 }
 
-pub async fn toggle_completion(conn: &Connection, task_id: i64, day: i64) -> Result<()> {
-    let mut rows = conn
-        .query(
-            "SELECT status FROM task_completion WHERE task_id = ? AND day = ?",
-            (task_id, day),
-        )
-        .await?;
-    let completed = rows.next().await?;
-
-    if completed.is_some() {
-        conn.execute(
-            "DELETE FROM task_completion WHERE task_id = ? AND day = ?",
-            (task_id, day),
+pub async fn restore_from_trash(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+) -> Result<()> {
+    let tx = conn.transaction().await?;
+    let restored = tx
+        .execute(
+            "UPDATE task SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL",
+            [task_id],
         )
         .await?;
-    } else {
-        conn.execute(
-            "INSERT INTO task_completion (task_id, day, status, done_at) VALUES (?, ?, 1, unixepoch())",
-            (task_id, day),
-        ).await?;
+    if restored == 0 {
+        return Ok(());
     }
-    update_task_stats(conn, task_id).await?;
+    refresh_task_weeks(&tx, clock, week_start, Some(task_id)).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
     Ok(())
 }
 
-pub async fn get_month_view(conn: &Connection, year: i32, month: u32) -> Result<Vec<MonthViewDay>> {
-    let start_of_month = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
-    let weekday_offset = start_of_month.weekday().num_days_from_monday() as i64;
-    let grid_start_date = start_of_month - chrono::Duration::days(weekday_offset);
-    let grid_size = 28;
-    let grid_end_date = grid_start_date + chrono::Duration::days(grid_size - 1);
-
-    let start_ts = grid_start_date
-        .and_hms_opt(0, 0, 0)
-        .unwrap()
-        .and_utc()
-        .timestamp();
-    let end_ts = grid_end_date
-        .and_hms_opt(0, 0, 0)
-        .unwrap()
-        .and_utc()
-        .timestamp();
-
-    let start_day = start_ts / 86400;
-    let end_day = end_ts / 86400;
-
-    struct Sched {
-        task_id: i64,
-        effective_from: i64,
-        effective_to: Option<i64>,
-        type_: String,
-        weekday_mask: Option<i64>,
-        monthday: Option<i64>,
-        title: String,
-        interval_days: Option<i64>,
-    }
-
-    let mut rows = conn.query(
-        "SELECT s.task_id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, t.title, s.interval_days
-        FROM task_schedule s
-        JOIN task t ON s.task_id = t.id
-        WHERE s.effective_from <= ? AND (s.effective_to IS NULL OR s.effective_to >= ?)",
-        (end_day, start_day),
-    ).await?;
-
-    let mut sched_list = Vec::new();
-    while let Some(row) = rows.next().await? {
-        sched_list.push(Sched {
-            task_id: row.get(0)?,
-            effective_from: row.get(1)?,
-            effective_to: row.get(2)?,
-            type_: row.get(3)?,
-            weekday_mask: row.get(4)?,
-            monthday: row.get(5)?,
-            title: row.get(6)?,
-            interval_days: row.get(7)?,
-        });
+/// Deletes for good what has been in the trash for more than
+/// `retention_days`, completions and stats go with it. 0 keeps everything.
+pub async fn purge_trash(conn: &Connection, retention_days: u32) -> Result<()> {
+    if retention_days == 0 {
+        return Ok(());
     }
+    let cutoff = Utc::now().timestamp() - i64::from(retention_days) * 86400;
+    conn.execute(
+        "DELETE FROM task WHERE deleted_at IS NOT NULL AND deleted_at < ?",
+        [cutoff],
+    )
+    .await?;
+    Ok(())
+}
 
-    let mut rows_comp = conn
-        .query(
-            "SELECT task_id, day FROM task_completion WHERE day BETWEEN ? AND ?",
-            (start_day, end_day),
+/// Takes a task off the list but keeps its history. Its schedule ends the
+/// day before, so nothing is due from the archive day on. A version that
+/// only starts today stays open, it never counts while the task is archived.
+pub async fn archive_task(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+) -> Result<()> {
+    let today = get_day_index(clock);
+    let tx = conn.transaction().await?;
+    let archived = tx
+        .execute(
+            "UPDATE task SET archived_at = ? WHERE id = ? AND archived_at IS NULL",
+            (Utc::now().timestamp(), task_id),
         )
         .await?;
-    let mut completions = std::collections::HashSet::new();
-    while let Some(row) = rows_comp.next().await? {
-        let tid: i64 = row.get(0)?;
-        let d: i64 = row.get(1)?;
-        completions.insert((tid, d));
+    if archived == 0 {
+        return Ok(());
     }
+    // Closing a version that starts today would end it before it starts
+    tx.execute(
+        "UPDATE task_schedule SET effective_to = ?1 - 1 WHERE task_id = ?2 AND effective_to IS NULL AND effective_from < ?1",
+        (today, task_id),
+    )
+    .await?;
+    tx.commit().await?;
 
-    let mut result = Vec::new();
-
-    for day in start_day..=end_day {
-        let mut due_count = 0;
-        let mut done_count = 0;
-
-        let d = DateTime::from_timestamp(day * 86400, 0)
-            .unwrap()
-            .date_naive();
-        let weekday_0 = d.weekday().num_days_from_monday() as i64;
-        let day_of_month = d.day() as i64;
-
-        let mut due_tasks = std::collections::HashSet::new();
-        let mut daily_tasks = Vec::new();
+    update_task_stats(conn, clock, week_start, task_id).await
+}
 
-        for s in &sched_list {
-            if day < s.effective_from {
-                continue;
-            }
-            if let Some(to) = s.effective_to {
-                if day > to {
-                    continue;
-                }
-            }
+/// Schedule versions (`s`) that make their task (`t`) due: not in the trash,
+/// and of archived tasks only the closed ones.
+const LIVE_VERSION: &str =
+    "t.deleted_at IS NULL AND (t.archived_at IS NULL OR s.effective_to IS NOT NULL)";
 
-            let is_due = match s.type_.as_str() {
-                "daily" => true,
-                "weekly" => {
-                    if let Some(mask) = s.weekday_mask {
-                        (mask >> weekday_0) & 1 == 1
-                    } else {
-                        false
-                    }
-                }
-                "monthly" => Some(day_of_month) == s.monthday,
-                "custom" => {
-                    if let Some(interval) = s.interval_days {
-                        if interval > 0 && day >= s.effective_from {
-                            (day - s.effective_from) % interval == 0
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                }
-                _ => false,
-            };
+/// Brings an archived task back from today on, with the schedule it had.
+/// The days it spent archived were never due.
+pub async fn unarchive_task(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+) -> Result<()> {
+    let today = get_day_index(clock);
+    let tx = conn.transaction().await?;
+    let restored = tx
+        .execute(
+            "UPDATE task SET archived_at = NULL WHERE id = ? AND archived_at IS NOT NULL",
+            [task_id],
+        )
+        .await?;
+    if restored == 0 {
+        return Ok(());
+    }
+    // A version left open by `archive_task` never counted, it starts over
+    let reopened = tx
+        .execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ? AND effective_to IS NULL",
+            (today, task_id),
+        )
+        .await?;
+    if reopened == 0 {
+        tx.execute(
+            "INSERT INTO task_schedule (task_id, effective_from, type, weekday_mask, monthday, interval_days, params_json)
+                SELECT task_id, ?, type, weekday_mask, monthday, interval_days, params_json
+                FROM task_schedule WHERE task_id = ?
+                ORDER BY effective_from DESC, id DESC LIMIT 1",
+            (today, task_id),
+        )
+        .await?;
+    }
+    tx.commit().await?;
 
-            if is_due {
-                if !due_tasks.contains(&s.task_id) {
-                    due_tasks.insert(s.task_id);
-                    due_count += 1;
+    update_task_stats(conn, clock, week_start, task_id).await
+}
 
-                    let is_done = completions.contains(&(s.task_id, day));
-                    if is_done {
-                        done_count += 1;
-                    }
+/// Archived tasks, most recently archived first, each with the last
+/// schedule it had.
+pub async fn list_archived_tasks(conn: &Connection) -> Result<Vec<TaskWithStats>> {
+    list_put_away(
+        conn,
+        "t.archived_at IS NOT NULL AND t.deleted_at IS NULL ORDER BY t.archived_at DESC",
+    )
+    .await
+}
 
-                    daily_tasks.push(MonthTask {
-                        id: s.task_id,
-                        title: s.title.clone(),
-                        is_done,
-                    });
-                }
-            }
-        }
+/// Tasks in the trash, most recently deleted first.
+pub async fn list_trash(conn: &Connection) -> Result<Vec<TaskWithStats>> {
+    list_put_away(conn, "t.deleted_at IS NOT NULL ORDER BY t.deleted_at DESC").await
+}
 
-        daily_tasks.sort_by_key(|t| t.id);
+/// Tasks that aren't on the list, with the last schedule each had. `filter`
+/// is the WHERE clause, ordering included.
+async fn list_put_away(conn: &Connection, filter: &str) -> Result<Vec<TaskWithStats>> {
+    let mut rows = conn
+        .query(
+            &format!(
+                "SELECT
+            t.id, t.title, t.notes, t.is_active, t.created_at, t.archived_at, t.target_amount, t.target_unit,
+            s.id, s.task_id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, s.interval_days, s.params_json,
+            st.current_streak, st.best_streak, st.freezes, t.deleted_at
+            FROM task t
+            JOIN task_schedule s ON s.id = (
+                SELECT id FROM task_schedule WHERE task_id = t.id
+                ORDER BY effective_from DESC, id DESC LIMIT 1
+            )
+            LEFT JOIN task_stats st ON t.id = st.task_id
+            WHERE {}",
+                filter
+            ),
+            (),
+        )
+        .await?;
 
-        result.push(MonthViewDay {
-            day,
-            due_count,
-            done_count,
-            all_done: due_count > 0 && due_count == done_count,
-            tasks: daily_tasks,
+    let mut tasks = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let task = Task {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            notes: row.get(2)?,
+            is_active: row.get(3)?,
+            created_at: row.get(4)?,
+            archived_at: row.get(5)?,
+            target_amount: row.get(6)?,
+            target_unit: row.get(7)?,
+            deleted_at: row.get(20)?,
+        };
+        let schedule = TaskSchedule::from_row(&row, 8)?;
+        tasks.push(TaskWithStats {
+            task,
+            schedule,
+            current_streak: row.get(17).unwrap_or(0),
+            best_streak: row.get(18).unwrap_or(0),
+            today_status: false,
+            status: CompletionStatus::Cleared,
+            progress: None,
+            is_due: false,
+            next_due: None,
+            quota: None,
+            overdue: false,
+            paused: false,
+            freezes: row.get(19).unwrap_or(0),
         });
     }
 
-    Ok(result)
+    Ok(tasks)
 }
 
-pub async fn edit_task(
+/// Drops the occurrence on `day` without changing the rule. A day with
+/// anything logged on it keeps its occurrence, see `ensure_unmarked`.
+pub async fn cancel_occurrence(
     conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
     task_id: i64,
-    new_title: String,
-    new_frequency_type: String,
-    new_weekday_mask: Option<i64>,
-    new_monthday: Option<i64>,
-    new_interval_days: Option<i64>,
+    day: i64,
 ) -> Result<()> {
-    conn.execute(
-        "UPDATE task SET title = ? WHERE id = ?",
-        (new_title.clone(), task_id),
-    )
-    .await?;
+    let version = occurrence_version(conn, clock, task_id, day).await?;
+    if !version.is_due(day) {
+        return Err(DbError::Invalid(format!(
+            "task {} is not due on day {}",
+            task_id, day
+        )));
+    }
+    ensure_unmarked(conn, task_id, day).await?;
+    let tx = conn.transaction().await?;
+    set_exception(&tx, task_id, day, Exception::Cancel, None).await?;
+    refresh_weeks(&tx, clock, week_start, day).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
 
-    let mut rows = conn.query(
-        "SELECT id, type, weekday_mask, monthday, interval_days FROM task_schedule WHERE task_id = ? AND effective_to IS NULL",
-        [task_id]
-    ).await?;
+/// Makes `day` due once, on top of what the rule says.
+pub async fn add_occurrence(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    day: i64,
+) -> Result<()> {
+    occurrence_version(conn, clock, task_id, day).await?;
+    let tx = conn.transaction().await?;
+    set_exception(&tx, task_id, day, Exception::Add, None).await?;
+    refresh_weeks(&tx, clock, week_start, day).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
 
-    if let Some(row) = rows.next().await? {
-        let current_id: i64 = row.get(0)?;
-        let current_type: String = row.get(1)?;
-        let current_mask: Option<i64> = row.get(2)?;
-        let current_monthday: Option<i64> = row.get(3)?;
+/// Moves the occurrence on `from_day` to `to_day`. One that was moved before
+/// keeps the day the rule has it on as its other end, so restoring either
+/// day still brings it back there.
+pub async fn move_occurrence(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    from_day: i64,
+    to_day: i64,
+) -> Result<()> {
+    let version = occurrence_version(conn, clock, task_id, from_day).await?;
+    if !version.is_due(from_day) {
+        return Err(DbError::Invalid(format!(
+            "task {} is not due on day {}",
+            task_id, from_day
+        )));
+    }
+    occurrence_version(conn, clock, task_id, to_day).await?;
+    ensure_unmarked(conn, task_id, from_day).await?;
+    let origin = match exception_at(conn, task_id, from_day).await? {
+        Some((Exception::Add, paired_day)) => paired_day,
+        _ => None,
+    };
+
+    let tx = conn.transaction().await?;
+    let rule_day = match origin {
+        Some(origin) => {
+            tx.execute(
+                "DELETE FROM schedule_exception WHERE task_id = ? AND day = ?",
+                (task_id, from_day),
+            )
+            .await?;
+            origin
+        }
+        None => from_day,
+    };
+    if rule_day == to_day {
+        // Back on the day the rule has it on
+        tx.execute(
+            "DELETE FROM schedule_exception WHERE task_id = ? AND day = ?",
+            (task_id, to_day),
+        )
+        .await?;
+    } else {
+        set_exception(&tx, task_id, rule_day, Exception::Cancel, Some(to_day)).await?;
+        set_exception(&tx, task_id, to_day, Exception::Add, Some(rule_day)).await?;
+    }
+    let first = from_day.min(to_day).min(rule_day);
+    refresh_weeks(&tx, clock, week_start, first).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Forgets any move, cancel or add on `day`, the rule applies again. For a
+/// move that is both ends, whichever of them `day` is.
+pub async fn restore_occurrence(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    day: i64,
+) -> Result<()> {
+    let Some((exception, paired_day)) = exception_at(conn, task_id, day).await? else {
+        return Ok(());
+    };
+    // The end that was an add loses its occurrence
+    let added = match exception {
+        Exception::Add => Some(day),
+        Exception::Cancel => paired_day,
+    };
+    if let Some(added) = added {
+        ensure_unmarked(conn, task_id, added).await?;
+    }
+
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "DELETE FROM schedule_exception WHERE task_id = ?1 AND (day = ?2 OR paired_day = ?2)",
+        (task_id, day),
+    )
+    .await?;
+    let first = paired_day.map_or(day, |paired_day| paired_day.min(day));
+    refresh_weeks(&tx, clock, week_start, first).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The schedule version covering `day`, with its exceptions, for changing a
+/// single occurrence. Pauses are left out, they don't move occurrences.
+async fn occurrence_version(
+    conn: &Connection,
+    clock: &DayClock,
+    task_id: i64,
+    day: i64,
+) -> Result<ScheduleVersion> {
+    let mut rows = conn
+        .query(
+            "SELECT * FROM task_schedule WHERE task_id = ? AND effective_from <= ? AND (effective_to IS NULL OR effective_to >= ?)",
+            (task_id, day, day),
+        )
+        .await?;
+    let Some(row) = rows.next().await? else {
+        return Err(DbError::Invalid(format!(
+            "task {} has no schedule on day {}",
+            task_id, day
+        )));
+    };
+    let schedule = TaskSchedule::from_row(&row, 0)?;
+    let version = ScheduleVersion::try_from(&schedule).map_err(DbError::Invalid)?;
+    if !version.has_occurrences() {
+        return Err(DbError::Invalid(format!(
+            "{} schedules have no single occurrences to change",
+            schedule.type_
+        )));
+    }
+    prepare_version(conn, version, get_day_index(clock), &[]).await
+}
+
+/// Writes the exception on `day`, with `paired_day` as the other end when it
+/// is half of a move. A move `day` was part of before is broken up, its
+/// other end stays on as a plain cancel or add.
+async fn set_exception(
+    conn: &Connection,
+    task_id: i64,
+    day: i64,
+    exception: Exception,
+    paired_day: Option<i64>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE schedule_exception SET paired_day = NULL
+            WHERE task_id = ?1 AND paired_day = ?2 AND day IS NOT ?3",
+        (task_id, day, paired_day),
+    )
+    .await?;
+    conn.execute(
+        "INSERT INTO schedule_exception (task_id, day, kind, paired_day) VALUES (?, ?, ?, ?)
+            ON CONFLICT(task_id, day) DO UPDATE SET
+                kind = excluded.kind,
+                paired_day = excluded.paired_day",
+        (task_id, day, exception.as_db(), paired_day),
+    )
+    .await?;
+    Ok(())
+}
+
+/// The exception on `day`, with the other end of the move it belongs to.
+async fn exception_at(
+    conn: &Connection,
+    task_id: i64,
+    day: i64,
+) -> Result<Option<(Exception, Option<i64>)>> {
+    let mut rows = conn
+        .query(
+            "SELECT kind, paired_day FROM schedule_exception WHERE task_id = ? AND day = ?",
+            (task_id, day),
+        )
+        .await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(None);
+    };
+    let paired_day: Option<i64> = row.get(1)?;
+    Ok(Exception::from_db(&row.get::<String>(0)?).map(|exception| (exception, paired_day)))
+}
+
+/// Refuses to take the occurrence off `day` while something is logged for
+/// it, the completion would be left behind on a day that isn't due.
+/// Clearing the day first makes dropping it the user's call.
+async fn ensure_unmarked(conn: &Connection, task_id: i64, day: i64) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT 1 FROM task_completion WHERE task_id = ? AND day = ?",
+            (task_id, day),
+        )
+        .await?;
+    if rows.next().await?.is_some() {
+        return Err(DbError::Invalid(format!(
+            "task {} is marked on day {}, clear it before moving or cancelling the occurrence",
+            task_id, day
+        )));
+    }
+    Ok(())
+}
+
+/// Pauses one task, or every task when `task_id` is None, from `start_day`
+/// through `end_day`. Without an end the pause lasts until it is ended.
+pub async fn schedule_pause(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: Option<i64>,
+    start_day: i64,
+    end_day: Option<i64>,
+) -> Result<i64> {
+    if end_day.is_some_and(|end| end < start_day) {
+        return Err(DbError::Invalid(
+            "pause can't end before it starts".to_string(),
+        ));
+    }
+
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "INSERT INTO pause (task_id, start_day, end_day) VALUES (?, ?, ?)",
+        (task_id, start_day, end_day),
+    )
+    .await?;
+    let id = tx.last_insert_rowid();
+
+    refresh_weeks(&tx, clock, week_start, start_day).await?;
+    rebuild_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Ends a pause early: it keeps the days already behind it and stops before
+/// today. One that hasn't started yet is dropped.
+pub async fn end_pause(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    pause_id: i64,
+) -> Result<()> {
+    let today = get_day_index(clock);
+    let mut rows = conn
+        .query(
+            "SELECT task_id, start_day, end_day FROM pause WHERE id = ?",
+            [pause_id],
+        )
+        .await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(());
+    };
+    let task_id: Option<i64> = row.get(0)?;
+    let start_day: i64 = row.get(1)?;
+    let end_day: Option<i64> = row.get(2)?;
+    drop(rows);
+
+    let tx = conn.transaction().await?;
+    if start_day >= today {
+        tx.execute("DELETE FROM pause WHERE id = ?", [pause_id])
+            .await?;
+    } else if end_day.is_none_or(|end| end >= today) {
+        tx.execute(
+            "UPDATE pause SET end_day = ? WHERE id = ?",
+            (today - 1, pause_id),
+        )
+        .await?;
+    } else {
+        return Ok(());
+    }
+
+    refresh_weeks(&tx, clock, week_start, start_day).await?;
+    rebuild_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn list_pauses(conn: &Connection) -> Result<Vec<Pause>> {
+    load_pauses(conn).await
+}
+
+pub async fn toggle_completion(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    day: i64,
+) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT status FROM task_completion WHERE task_id = ? AND day = ?",
+            (task_id, day),
+        )
+        .await?;
+    let status = match rows.next().await? {
+        Some(row) => CompletionStatus::from_db(row.get(0)?),
+        None => CompletionStatus::Cleared,
+    };
+    drop(rows);
+
+    // The day and the stats that follow from it change together
+    let tx = conn.transaction().await?;
+    if status.is_satisfied() {
+        tx.execute(
+            "DELETE FROM task_completion WHERE task_id = ? AND day = ?",
+            (task_id, day),
+        )
+        .await?;
+    } else {
+        // Ticking a quantitative task fills it up to its target
+        tx.execute(
+            "INSERT INTO task_completion (task_id, day, status, done_at, value)
+                VALUES (?, ?, 1, unixepoch(), (SELECT target_amount FROM task WHERE id = ?))
+            ON CONFLICT(task_id, day) DO UPDATE SET
+                status = 1, done_at = unixepoch(), value = MAX(COALESCE(value, 0), COALESCE(excluded.value, 0))",
+            (task_id, day, task_id),
+        ).await?;
+    }
+    refresh_completion_weeks(&tx, clock, week_start, task_id, day).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_completion_status(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    day: i64,
+    status: CompletionStatus,
+) -> Result<()> {
+    if status == CompletionStatus::InProgress {
+        return Err(DbError::Invalid(
+            "in_progress is derived from logged progress, use increment_progress".to_string(),
+        ));
+    }
+
+    let tx = conn.transaction().await?;
+    if status == CompletionStatus::Cleared {
+        tx.execute(
+            "DELETE FROM task_completion WHERE task_id = ? AND day = ?",
+            (task_id, day),
+        )
+        .await?;
+    } else {
+        tx.execute(
+            "INSERT INTO task_completion (task_id, day, status, done_at) VALUES (?, ?, ?, unixepoch())
+            ON CONFLICT(task_id, day) DO UPDATE SET status = excluded.status, done_at = excluded.done_at",
+            (task_id, day, status.to_db()),
+        )
+        .await?;
+    }
+    refresh_completion_weeks(&tx, clock, week_start, task_id, day).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Adds `amount` to the progress logged for `day` (negative amounts correct a
+/// mistake). The day flips to done once the task's target is reached; tasks
+/// without a target behave as if the target was 1.
+pub async fn increment_progress(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    day: i64,
+    amount: f64,
+) -> Result<()> {
+    if !amount.is_finite() || amount == 0.0 {
+        return Err(DbError::Invalid(format!(
+            "progress amount must be a non-zero number, got {}",
+            amount
+        )));
+    }
+    // Value and status change together or not at all
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "INSERT INTO task_completion (task_id, day, status, value) VALUES (?, ?, 0, ?)
+        ON CONFLICT(task_id, day) DO UPDATE SET value = COALESCE(value, 0) + excluded.value",
+        (task_id, day, amount),
+    )
+    .await?;
+
+    tx.execute(
+        "DELETE FROM task_completion WHERE task_id = ? AND day = ? AND value <= 0",
+        (task_id, day),
+    )
+    .await?;
+
+    tx.execute(
+        "UPDATE task_completion SET
+            status = CASE WHEN value >= target THEN 1 ELSE 0 END,
+            done_at = CASE WHEN value >= target THEN COALESCE(done_at, unixepoch()) END
+        FROM (SELECT COALESCE(target_amount, 1) AS target FROM task WHERE id = ?)
+        WHERE task_id = ? AND day = ?",
+        (task_id, task_id, day),
+    )
+    .await?;
+
+    refresh_completion_weeks(&tx, clock, week_start, task_id, day).await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Every logged (task, day) in `start_day..=end_day`.
+async fn load_completions(
+    conn: &Connection,
+    start_day: i64,
+    end_day: i64,
+) -> Result<HashMap<(i64, i64), Completion>> {
+    let mut rows = conn
+        .query(
+            "SELECT task_id, day, status, value FROM task_completion WHERE day BETWEEN ? AND ?",
+            (start_day, end_day),
+        )
+        .await?;
+    let mut completions = HashMap::new();
+    while let Some(row) = rows.next().await? {
+        let tid: i64 = row.get(0)?;
+        let d: i64 = row.get(1)?;
+        completions.insert(
+            (tid, d),
+            Completion {
+                status: CompletionStatus::from_db(row.get(2)?),
+                value: row.get(3)?,
+            },
+        );
+    }
+    Ok(completions)
+}
+
+/// Every pause, global ones first.
+async fn load_pauses(conn: &Connection) -> Result<Vec<Pause>> {
+    let mut rows = conn
+        .query(
+            "SELECT id, task_id, start_day, end_day FROM pause ORDER BY task_id IS NOT NULL, start_day",
+            (),
+        )
+        .await?;
+    let mut pauses = Vec::new();
+    while let Some(row) = rows.next().await? {
+        pauses.push(Pause {
+            id: row.get(0)?,
+            task_id: row.get(1)?,
+            start_day: row.get(2)?,
+            end_day: row.get(3)?,
+        });
+    }
+    Ok(pauses)
+}
+
+/// Single day overrides of `task_id`'s schedule.
+async fn load_exceptions(conn: &Connection, task_id: i64) -> Result<HashMap<i64, Exception>> {
+    let mut rows = conn
+        .query(
+            "SELECT day, kind FROM schedule_exception WHERE task_id = ?",
+            [task_id],
+        )
+        .await?;
+    let mut exceptions = HashMap::new();
+    while let Some(row) = rows.next().await? {
+        let day: i64 = row.get(0)?;
+        if let Some(exception) = Exception::from_db(&row.get::<String>(1)?) {
+            exceptions.insert(day, exception);
+        }
+    }
+    Ok(exceptions)
+}
+
+/// Day ranges `task_id` is on pause for, its own and global ones.
+fn pauses_for(pauses: &[Pause], task_id: i64) -> Vec<RangeInclusive<i64>> {
+    pauses
+        .iter()
+        .filter(|p| p.task_id.is_none_or(|id| id == task_id))
+        .map(Pause::days)
+        .collect()
+}
+
+/// Attaches what a schedule needs beyond its own row: pauses, moved
+/// occurrences, and for completion anchored schedules the days it was done
+/// on.
+async fn prepare_version(
+    conn: &Connection,
+    version: ScheduleVersion,
+    today: i64,
+    pauses: &[Pause],
+) -> Result<ScheduleVersion> {
+    let task_pauses = pauses_for(pauses, version.task_id);
+    let exceptions = load_exceptions(conn, version.task_id).await?;
+    let version = version.with_pauses(task_pauses).with_exceptions(exceptions);
+    if !version.needs_history() {
+        return Ok(version);
+    }
+    // A schedule anchored to completions counts on from the last one, even
+    // if that was under an earlier version. A one-off only cares about its own.
+    let since = match version.recurrence {
+        Recurrence::AfterCompletion { .. } => "(SELECT COALESCE(MAX(day), ?2) FROM task_completion WHERE task_id = ?1 AND day < ?2 AND status IN (1, 2))",
+        _ => "?2",
+    };
+    let mut rows = conn
+        .query(
+            &format!("SELECT day FROM task_completion WHERE task_id = ?1 AND day >= {since} AND status IN (1, 2) ORDER BY day"),
+            (version.task_id, version.effective_from),
+        )
+        .await?;
+    let mut done = Vec::new();
+    while let Some(row) = rows.next().await? {
+        done.push(row.get::<i64>(0)?);
+    }
+    Ok(version.with_history(History { done, today }))
+}
+
+/// Days a streak freeze was spent on between `start_day` and `end_day`, as
+/// (task_id, day).
+async fn load_frozen(
+    conn: &Connection,
+    start_day: i64,
+    end_day: i64,
+) -> Result<std::collections::HashSet<(i64, i64)>> {
+    let mut rows = conn
+        .query(
+            "SELECT task_id, day FROM streak_freeze WHERE day BETWEEN ? AND ?",
+            (start_day, end_day),
+        )
+        .await?;
+    let mut frozen = std::collections::HashSet::new();
+    while let Some(row) = rows.next().await? {
+        frozen.insert((row.get(0)?, row.get(1)?));
+    }
+    Ok(frozen)
+}
+
+/// Days marked done in `period`, the unit quotas are counted in.
+async fn count_done(conn: &Connection, task_id: i64, period: &RangeInclusive<i64>) -> Result<i64> {
+    let mut rows = conn
+        .query(
+            "SELECT COUNT(*) FROM task_completion WHERE task_id = ? AND day BETWEEN ? AND ? AND status = 1",
+            (task_id, *period.start(), *period.end()),
+        )
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok(row.get(0)?),
+        None => Ok(0),
+    }
+}
+
+pub async fn get_month_view(
+    conn: &Connection,
+    clock: &DayClock,
+    year: i32,
+    month: u32,
+    week_start: Weekday,
+) -> Result<Vec<MonthViewDay>> {
+    let start_of_month = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+    let weekday_offset = start_of_month.weekday().days_since(week_start) as i64;
+    let grid_start_date = start_of_month - chrono::Duration::days(weekday_offset);
+    let grid_size = 28;
+    let grid_end_date = grid_start_date + chrono::Duration::days(grid_size - 1);
+
+    let start_day = clock::date_to_day(grid_start_date);
+    let end_day = clock::date_to_day(grid_end_date);
+
+    struct Sched {
+        version: ScheduleVersion,
+        title: String,
+        target_amount: Option<f64>,
+    }
+
+    let mut rows = conn.query(
+        &format!("SELECT s.id, s.task_id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, s.interval_days, s.params_json, t.title, t.target_amount
+        FROM task_schedule s
+        JOIN task t ON s.task_id = t.id
+        WHERE {} AND s.effective_from <= ? AND (s.effective_to IS NULL OR s.effective_to >= ?)", LIVE_VERSION),
+        (end_day, start_day),
+    ).await?;
+
+    let mut parsed = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let schedule = TaskSchedule::from_row(&row, 0)?;
+        // Rows that can't be evaluated are never due
+        if let Ok(version) = ScheduleVersion::try_from(&schedule) {
+            parsed.push((version, row.get(9)?, row.get(10)?));
+        }
+    }
+
+    // Completion anchored schedules project their future due days from today
+    let today = get_day_index(clock);
+    let pauses = load_pauses(conn).await?;
+    let mut sched_list = Vec::new();
+    for (version, title, target_amount) in parsed {
+        sched_list.push(Sched {
+            version: prepare_version(conn, version, today, &pauses).await?,
+            title,
+            target_amount,
+        });
+    }
+
+    let mut due_by_day: HashMap<i64, Vec<&Sched>> = HashMap::new();
+    let mut paused_by_day: HashMap<i64, std::collections::HashSet<i64>> = HashMap::new();
+    for s in &sched_list {
+        for day in s.version.occurrences(start_day..=end_day) {
+            due_by_day.entry(day).or_default().push(s);
+        }
+        for day in s.version.paused_occurrences(start_day..=end_day) {
+            paused_by_day
+                .entry(day)
+                .or_default()
+                .insert(s.version.task_id);
+        }
+    }
+
+    let completions = load_completions(conn, start_day, end_day).await?;
+    let frozen = load_frozen(conn, start_day, end_day).await?;
+
+    let mut result = Vec::new();
+
+    for day in start_day..=end_day {
+        let mut due_count = 0;
+        let mut done_count = 0;
+        let mut skipped_count = 0;
+        let mut progress_sum = 0.0;
+
+        let mut due_tasks = std::collections::HashSet::new();
+        let mut daily_tasks = Vec::new();
+
+        for s in due_by_day.get(&day).into_iter().flatten() {
+            let task_id = s.version.task_id;
+            if due_tasks.insert(task_id) {
+                let completion = completions.get(&(task_id, day));
+                let status = completion.map_or(CompletionStatus::Cleared, |c| c.status);
+                let progress = completion.and_then(|c| c.value);
+
+                // Quota tasks can be done on any day but are only owed once
+                // per period, so an untouched day doesn't count against it
+                let optional =
+                    s.version.recurrence.quota().is_some() && status == CompletionStatus::Cleared;
+                if !optional {
+                    due_count += 1;
+                }
+                match status {
+                    CompletionStatus::Done => done_count += 1,
+                    CompletionStatus::Skipped => skipped_count += 1,
+                    CompletionStatus::InProgress | CompletionStatus::Cleared => {}
+                }
+                progress_sum += match (status, progress) {
+                    (CompletionStatus::InProgress, Some(value)) => {
+                        (value / s.target_amount.unwrap_or(1.0)).clamp(0.0, 1.0)
+                    }
+                    (status, _) if status.is_satisfied() => 1.0,
+                    _ => 0.0,
+                };
+
+                daily_tasks.push(MonthTask {
+                    id: task_id,
+                    title: s.title.clone(),
+                    is_done: status == CompletionStatus::Done,
+                    status,
+                    progress,
+                    target: s.target_amount,
+                    frozen: frozen.contains(&(task_id, day)),
+                });
+            }
+        }
+
+        daily_tasks.sort_by_key(|t| t.id);
+
+        result.push(MonthViewDay {
+            day,
+            paused: pauses
+                .iter()
+                .any(|p| p.task_id.is_none() && p.days().contains(&day)),
+            paused_count: paused_by_day.get(&day).map_or(0, |ids| ids.len() as i64),
+            due_count,
+            done_count,
+            skipped_count,
+            all_done: due_count > 0 && due_count == done_count + skipped_count,
+            progress: if due_count > 0 {
+                progress_sum / due_count as f64
+            } else {
+                0.0
+            },
+            tasks: daily_tasks,
+        });
+    }
+
+    Ok(result)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn edit_task(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    new_title: String,
+    new_frequency_type: String,
+    new_weekday_mask: Option<i64>,
+    new_monthday: Option<i64>,
+    new_interval_days: Option<i64>,
+    new_params_json: Option<String>,
+    new_target_amount: Option<f64>,
+    new_target_unit: Option<String>,
+) -> Result<()> {
+    let mut rows = conn.query(
+        "SELECT id, type, weekday_mask, monthday, interval_days, params_json FROM task_schedule WHERE task_id = ? AND effective_to IS NULL",
+        [task_id]
+    ).await?;
+
+    let mut schedule_change = None;
+    if let Some(row) = rows.next().await? {
+        let current_id: i64 = row.get(0)?;
+        let current_type: String = row.get(1)?;
+        let current_mask: Option<i64> = row.get(2)?;
+        let current_monthday: Option<i64> = row.get(3)?;
         let current_interval: Option<i64> = row.get(4)?;
+        let current_params: Option<String> = row.get(5)?;
 
         let schedule_changed = current_type != new_frequency_type
             || current_mask != new_weekday_mask
             || current_monthday != new_monthday
-            || current_interval != new_interval_days;
+            || current_interval != new_interval_days
+            || current_params != new_params_json;
 
         if schedule_changed {
-            let today = get_day_index();
+            let today = get_day_index(clock);
+            // Validate before writing anything so a bad rule leaves the task untouched
+            validate_schedule(
+                &new_frequency_type,
+                new_weekday_mask,
+                new_monthday,
+                new_interval_days,
+                new_params_json.as_deref(),
+                today,
+            )?;
+            schedule_change = Some((current_id, today));
+        }
+    }
+    drop(rows);
+
+    let mut rows = conn
+        .query("SELECT target_amount FROM task WHERE id = ?", [task_id])
+        .await?;
+    let target_changed = match rows.next().await? {
+        Some(row) => row.get::<Option<f64>>(0)? != new_target_amount,
+        None => false,
+    };
+    drop(rows);
+
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "UPDATE task SET title = ?, target_amount = ?, target_unit = ? WHERE id = ?",
+        (
+            new_title.clone(),
+            new_target_amount,
+            new_target_unit,
+            task_id,
+        ),
+    )
+    .await?;
+
+    if target_changed {
+        // The new target holds from today on, days before it were judged
+        // by the target they were logged against
+        let today = get_day_index(clock);
+        tx.execute(
+            "UPDATE task_completion SET
+                status = CASE WHEN value >= target THEN 1 ELSE 0 END,
+                done_at = CASE WHEN value >= target THEN COALESCE(done_at, unixepoch()) END
+            FROM (SELECT COALESCE(target_amount, 1) AS target FROM task WHERE id = ?)
+            WHERE task_id = ? AND day >= ? AND value IS NOT NULL AND status IN (0, 1)",
+            (task_id, task_id, today),
+        )
+        .await?;
+        refresh_weeks(&tx, clock, week_start, today).await?;
+    }
+
+    if let Some((current_id, today)) = schedule_change {
+        tx.execute(
+            "UPDATE task_schedule SET effective_to = ? WHERE id = ?",
+            (today - 1, current_id),
+        )
+        .await?;
+
+        tx.execute(
+            "INSERT INTO task_schedule (task_id, effective_from, type, weekday_mask, monthday, interval_days, params_json)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            (
+                task_id,
+                today,
+                new_frequency_type,
+                new_weekday_mask,
+                new_monthday,
+                new_interval_days,
+                new_params_json,
+            ),
+        ).await?;
+    }
+    if schedule_change.is_some() || target_changed {
+        write_task_stats(&tx, clock, week_start, task_id).await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn update_task_stats(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+) -> Result<()> {
+    let tx = conn.transaction().await?;
+    write_task_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// `update_task_stats` on a transaction the caller already holds, so the
+/// stats land together with the change they follow from.
+async fn write_task_stats(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+) -> Result<()> {
+    let today = get_day_index(clock);
+    let versions = load_versions(conn, task_id, today).await?;
+    if versions.is_empty() {
+        return Ok(());
+    }
+
+    let mut rows_comp = conn
+        .query(
+            "SELECT day, status FROM task_completion WHERE task_id = ? ORDER BY day DESC",
+            [task_id],
+        )
+        .await?;
+
+    let mut completions = HashMap::new();
+    while let Some(row) = rows_comp.next().await? {
+        let day: i64 = row.get(0)?;
+        completions.insert(day, CompletionStatus::from_db(row.get(1)?));
+    }
+
+    let mut marks = streak_marks(&versions, &completions, today, week_start);
+    marks.reverse();
+    let tally = Tally::count(&marks);
+    let last_completed_day = completions
+        .iter()
+        .filter(|(_, status)| **status == CompletionStatus::Done)
+        .map(|(day, _)| *day)
+        .max();
+
+    conn.execute(
+        "INSERT INTO task_stats (task_id, current_streak, best_streak, last_completed_day, freezes) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(task_id) DO UPDATE SET
+                current_streak = excluded.current_streak,
+                best_streak = excluded.best_streak,
+                last_completed_day = excluded.last_completed_day,
+                freezes = excluded.freezes,
+                updated_at = unixepoch()",
+        (task_id, tally.current, tally.best, last_completed_day, tally.freezes),
+    ).await?;
+    conn.execute("DELETE FROM streak_freeze WHERE task_id = ?", [task_id])
+        .await?;
+    for day in tally.frozen {
+        conn.execute(
+            "INSERT INTO streak_freeze (task_id, day) VALUES (?, ?)",
+            (task_id, day),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Every schedule version of a task, newest first, ready to evaluate.
+async fn load_versions(
+    conn: &Connection,
+    task_id: i64,
+    today: i64,
+) -> Result<Vec<ScheduleVersion>> {
+    let mut rows = conn
+        .query(
+            "SELECT s.* FROM task_schedule s
+            JOIN task t ON s.task_id = t.id
+            WHERE s.task_id = ? AND (t.archived_at IS NULL OR s.effective_to IS NOT NULL)
+            ORDER BY s.effective_from DESC, s.id DESC",
+            [task_id],
+        )
+        .await?;
+
+    let mut versions = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let schedule = TaskSchedule::from_row(&row, 0)?;
+        if let Ok(version) = ScheduleVersion::try_from(&schedule) {
+            versions.push(version);
+        }
+    }
+
+    let pauses = load_pauses(conn).await?;
+    let mut prepared = Vec::new();
+    for version in versions {
+        prepared.push(prepare_version(conn, version, today, &pauses).await?);
+    }
+    Ok(prepared)
+}
+
+/// A freeze is earned for this many hits in a row.
+const FREEZE_EARN_EVERY: i64 = 7;
+/// Freezes a task can hold at once.
+const MAX_FREEZES: i64 = 2;
+
+/// Streaks and freezes from a task's marks, oldest first.
+#[derive(Debug, Default)]
+struct Tally {
+    current: i64,
+    best: i64,
+    freezes: i64,     // left to spend
+    frozen: Vec<i64>, // days a freeze was spent on
+}
+
+impl Tally {
+    /// A miss spends a freeze if there is one, and only breaks the streak
+    /// when there isn't.
+    fn count(marks: &[(i64, Mark)]) -> Tally {
+        let mut tally = Tally::default();
+        let mut towards_freeze = 0;
+        for (day, mark) in marks {
+            match mark {
+                Mark::Hit => {
+                    tally.current += 1;
+                    tally.best = tally.best.max(tally.current);
+                    towards_freeze += 1;
+                    if towards_freeze == FREEZE_EARN_EVERY {
+                        towards_freeze = 0;
+                        tally.freezes = (tally.freezes + 1).min(MAX_FREEZES);
+                    }
+                }
+                Mark::Neutral => {}
+                Mark::Miss if tally.freezes > 0 => {
+                    tally.freezes -= 1;
+                    tally.frozen.push(*day);
+                }
+                Mark::Miss => {
+                    tally.current = 0;
+                    towards_freeze = 0;
+                }
+            }
+        }
+        tally
+    }
+}
+
+/// How one due day, or one quota period, counts towards a streak.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mark {
+    Hit,
+    /// Neither adds nor breaks: skipped, still open, or paused.
+    Neutral,
+    Miss,
+}
+
+/// Marks for everything due up to today, newest first, with the due day (the
+/// last day for a quota period). Each schedule
+/// version is judged by its own rule over the days it was in effect, so
+/// editing a schedule carries the streak over instead of starting again.
+fn streak_marks(
+    versions: &[ScheduleVersion],
+    completions: &HashMap<i64, CompletionStatus>,
+    today: i64,
+    week_start: Weekday,
+) -> Vec<(i64, Mark)> {
+    let mut marks = Vec::new();
+    for version in versions {
+        let end = version.effective_to.map_or(today, |to| to.min(today));
+        let start = version.effective_from;
+        if end < start {
+            continue;
+        }
+
+        if let Some((times, period)) = version.recurrence.quota() {
+            let mut range = period.containing(end, week_start);
+            while *range.end() >= start {
+                let done = quota_window(version, &range, today)
+                    .filter(|day| completions.get(day) == Some(&CompletionStatus::Done))
+                    .count() as i64;
+                marks.push((
+                    *range.end(),
+                    quota_mark(version, &range, done, times, today),
+                ));
+                range = period.containing(range.start() - 1, week_start);
+            }
+        } else {
+            for day in (start..=end).rev() {
+                if !version.is_due(day) {
+                    continue;
+                }
+                // Skipped days are neutral: they keep the streak alive without adding to it
+                let mark = match completions.get(&day) {
+                    Some(CompletionStatus::Done) => Mark::Hit,
+                    Some(CompletionStatus::Skipped) => Mark::Neutral,
+                    _ if day == today => Mark::Neutral,
+                    _ => Mark::Miss,
+                };
+                marks.push((day, mark));
+            }
+        }
+    }
+    marks
+}
+
+/// The days of quota `period` whose completions count for `version`.
+fn quota_window(
+    version: &ScheduleVersion,
+    period: &RangeInclusive<i64>,
+    today: i64,
+) -> RangeInclusive<i64> {
+    let end = version.effective_to.map_or(today, |to| to.min(today));
+    (*period.start()).max(version.effective_from)..=(*period.end()).min(end)
+}
+
+/// How one quota period with `done` completions counts. A period counts once
+/// it is met. One still running, cut off by a schedule change or with a
+/// pause in it can't break the streak. Shared by the task streaks and the
+/// weekly one so the two never disagree.
+fn quota_mark(
+    version: &ScheduleVersion,
+    period: &RangeInclusive<i64>,
+    done: i64,
+    times: i64,
+    today: i64,
+) -> Mark {
+    let open = *period.end() >= today
+        || *period.start() < version.effective_from
+        || version.effective_to.is_some_and(|to| *period.end() > to);
+    if done >= times {
+        Mark::Hit
+    } else if open || period.clone().any(|day| version.is_paused(day)) {
+        Mark::Neutral
+    } else {
+        Mark::Miss
+    }
+}
+
+/// What was due in one week and how much of it got done, the row kept in
+/// `week_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct WeekSummary {
+    due_count: i64,
+    done_count: i64,
+    paused: bool, // something due fell on a pause
+}
+
+impl WeekSummary {
+    /// `None` when the week had nothing due because of a pause, such a week
+    /// neither counts nor breaks the weekly streak.
+    fn perfect(&self) -> Option<bool> {
+        match self.due_count {
+            0 if self.paused => None,
+            0 => Some(false),
+            _ => Some(self.done_count == self.due_count),
+        }
+    }
+}
+
+async fn summarize_week(
+    conn: &Connection,
+    week_start_day: i64,
+    week_start: Weekday,
+    today: i64,
+) -> Result<WeekSummary> {
+    let start_day = week_start_day;
+    let end_day = week_start_day + 6;
+
+    let mut rows = conn.query(
+        &format!("SELECT s.id, s.task_id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, s.interval_days, s.params_json
+            FROM task_schedule s
+            JOIN task t ON s.task_id = t.id
+            WHERE {} AND s.effective_from <= ? AND (s.effective_to IS NULL OR s.effective_to >= ?)", LIVE_VERSION),
+        (end_day, start_day),
+    ).await?;
+
+    let mut scheds = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let schedule = TaskSchedule::from_row(&row, 0)?;
+        if let Ok(version) = ScheduleVersion::try_from(&schedule) {
+            scheds.push(version);
+        }
+    }
+    let pauses = load_pauses(conn).await?;
+    let mut prepared = Vec::new();
+    for s in scheds {
+        prepared.push(prepare_version(conn, s, today, &pauses).await?);
+    }
+    let scheds = prepared;
+
+    let completions = load_completions(conn, start_day, end_day).await?;
+
+    let mut summary = WeekSummary::default();
+
+    for s in &scheds {
+        if let Some((times, period)) = s.recurrence.quota() {
+            // A quota is judged in the week its period ends in, by the
+            // version in effect on that day
+            let range = period.containing(start_day, week_start);
+            if *range.end() > end_day || !s.in_effect(*range.end()) {
+                continue;
+            }
+            let done = count_done(conn, s.task_id, &quota_window(s, &range, today)).await?;
+            match quota_mark(s, &range, done, times, today) {
+                Mark::Hit => {
+                    summary.due_count += 1;
+                    summary.done_count += 1;
+                }
+                Mark::Miss => summary.due_count += 1,
+                Mark::Neutral => {
+                    if range.clone().any(|day| s.is_paused(day)) {
+                        summary.paused = true;
+                    }
+                }
+            }
+            continue;
+        }
+        for day in s.occurrences(start_day..=end_day) {
+            summary.due_count += 1;
+            let satisfied = completions
+                .get(&(s.task_id, day))
+                .is_some_and(|c| c.status.is_satisfied());
+            if satisfied {
+                summary.done_count += 1;
+            }
+        }
+        if s.paused_occurrences(start_day..=end_day).next().is_some() {
+            summary.paused = true;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Summaries of finished weeks. Only weeks that ended before today are kept,
+/// nothing effective from today on can change them; writes into the past
+/// call `refresh_weeks`.
+async fn load_week_stats(conn: &Connection, from_week: i64) -> Result<HashMap<i64, WeekSummary>> {
+    let mut rows = conn
+        .query(
+            "SELECT week_start_day, due_count, done_count, paused FROM week_stats WHERE week_start_day >= ?",
+            [from_week],
+        )
+        .await?;
+    let mut weeks = HashMap::new();
+    while let Some(row) = rows.next().await? {
+        weeks.insert(
+            row.get::<i64>(0)?,
+            WeekSummary {
+                due_count: row.get(1)?,
+                done_count: row.get(2)?,
+                paused: row.get::<i64>(3)? != 0,
+            },
+        );
+    }
+    Ok(weeks)
+}
+
+async fn store_week_stats(
+    conn: &Connection,
+    week_start_day: i64,
+    summary: WeekSummary,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO week_stats (week_start_day, all_done, due_count, done_count, paused) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(week_start_day) DO UPDATE SET
+                all_done = excluded.all_done,
+                due_count = excluded.due_count,
+                done_count = excluded.done_count,
+                paused = excluded.paused,
+                updated_at = unixepoch()",
+        (
+            week_start_day,
+            summary.perfect() == Some(true),
+            summary.due_count,
+            summary.done_count,
+            summary.paused,
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// The first day of the week `day` falls in.
+fn week_of(day: i64, week_start: Weekday) -> i64 {
+    day - clock::day_to_date(day).weekday().days_since(week_start) as i64
+}
+
+/// The finished weeks the weekly streak looks back over, newest first.
+fn finished_weeks(today: i64, week_start: Weekday) -> impl Iterator<Item = i64> {
+    let this_week = week_of(today, week_start);
+    (1..=260)
+        .map(move |n| this_week - 7 * n)
+        .take_while(|week| *week >= 0)
+}
 
-            conn.execute(
-                "UPDATE task_schedule SET effective_to = ? WHERE id = ?",
-                (today - 1, current_id),
-            )
-            .await?;
+/// Summarizes again every stored week that ends on or after `day`, on the
+/// writer's transaction so the weeks change together with the rows. Later
+/// weeks go too: completion anchored schedules move what is due after the
+/// day that changed.
+pub async fn refresh_weeks(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    day: i64,
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM week_stats WHERE week_start_day + 6 >= ?",
+        [day],
+    )
+    .await?;
+    fill_weeks(conn, clock, week_start).await
+}
 
-            conn.execute(
-                "INSERT INTO task_schedule (task_id, effective_from, type, weekday_mask, monthday, interval_days)
-                 VALUES (?, ?, ?, ?, ?, ?)",
-                (
-                    task_id,
-                    today,
-                    new_frequency_type,
-                    new_weekday_mask,
-                    new_monthday,
-                    new_interval_days,
-                ),
-            ).await?;
+/// Stores the summary of every finished week that has none yet, from the
+/// first week anything was scheduled in. `get_weekly_streak` only reads, the
+/// weeks that ended since the last write are filled here.
+async fn fill_weeks(conn: &Connection, clock: &DayClock, week_start: Weekday) -> Result<()> {
+    let mut rows = conn
+        .query("SELECT MIN(effective_from) FROM task_schedule", ())
+        .await?;
+    let first: Option<i64> = match rows.next().await? {
+        Some(row) => row.get(0)?,
+        None => None,
+    };
+    drop(rows);
+    let Some(first) = first else {
+        return Ok(());
+    };
 
-            conn.execute(
-                "UPDATE task_stats SET current_streak = 0, last_completed_day = NULL WHERE task_id = ?",
-                [task_id],
-            ).await?;
+    let today = get_day_index(clock);
+    let first_week = week_of(first, week_start);
+    let stored = load_week_stats(conn, first_week).await?;
+    for week in finished_weeks(today, week_start).take_while(|week| *week >= first_week) {
+        if !stored.contains_key(&week) {
+            let summary = summarize_week(conn, week, week_start, today).await?;
+            store_week_stats(conn, week, summary).await?;
         }
     }
-
     Ok(())
 }
 
-fn is_task_due(schedule: &TaskSchedule, day: i64) -> bool {
-    let d = DateTime::from_timestamp(day * 86400, 0)
-        .unwrap()
-        .date_naive();
+/// `refresh_weeks` for a completion of `task_id` on `day`.
+pub async fn refresh_completion_weeks(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: i64,
+    day: i64,
+) -> Result<()> {
+    let from = completion_counts_from(conn, task_id, day).await?;
+    refresh_weeks(conn, clock, week_start, from).await
+}
 
-    if day < schedule.effective_from {
-        return false;
+/// `refresh_weeks` for a change to the whole history of `task_id`, or of any
+/// task when None, like deleting it.
+pub async fn refresh_task_weeks(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: Option<i64>,
+) -> Result<()> {
+    match task_counts_from(conn, task_id).await? {
+        Some(from) => refresh_weeks(conn, clock, week_start, from).await,
+        None => Ok(()),
     }
+}
 
-    match schedule.type_.as_str() {
-        "daily" => true,
-        "weekly" => {
-            if let Some(mask) = schedule.weekday_mask {
-                let weekday = d.weekday().num_days_from_monday() as i64;
-                (mask & (1 << weekday)) != 0
-            } else {
-                false
-            }
-        }
-        "monthly" => {
-            if let Some(mday) = schedule.monthday {
-                d.day() as i64 == mday
-            } else {
-                false
-            }
-        }
-        "custom" => {
-            if let Some(interval) = schedule.interval_days {
-                if interval <= 0 {
-                    return false;
-                }
-                if day >= schedule.effective_from {
-                    (day - schedule.effective_from) % interval == 0
-                } else {
-                    false
-                }
-            } else {
-                false
+/// The first day whose week a completion of `task_id` on `day` counts
+/// towards. A one-off is due on the day it gets done, so one done late also
+/// counts in the week it was planned in.
+pub async fn completion_counts_from(conn: &Connection, task_id: i64, day: i64) -> Result<i64> {
+    let mut rows = conn
+        .query(
+            "SELECT * FROM task_schedule WHERE task_id = ? AND type = 'once'",
+            [task_id],
+        )
+        .await?;
+    let mut from = day;
+    while let Some(row) = rows.next().await? {
+        let schedule = TaskSchedule::from_row(&row, 0)?;
+        if let Ok(version) = ScheduleVersion::try_from(&schedule) {
+            if let Recurrence::Once { due, .. } = version.recurrence {
+                from = from.min(due);
             }
         }
-        _ => false,
     }
+    Ok(from)
 }
 
-async fn update_task_stats(conn: &Connection, task_id: i64) -> Result<()> {
+/// The first day `task_id`, or any task when None, had a schedule.
+pub async fn task_counts_from(conn: &Connection, task_id: Option<i64>) -> Result<Option<i64>> {
     let mut rows = conn
         .query(
-            "SELECT * FROM task_schedule WHERE task_id = ? AND effective_to IS NULL",
+            "SELECT MIN(effective_from) FROM task_schedule WHERE ?1 IS NULL OR task_id = ?1",
             [task_id],
         )
         .await?;
+    Ok(match rows.next().await? {
+        Some(row) => row.get(0)?,
+        None => None,
+    })
+}
 
-    let schedule = if let Some(row) = rows.next().await? {
-        TaskSchedule {
-            id: row.get(0)?,
-            task_id: row.get(1)?,
-            effective_from: row.get(2)?,
-            effective_to: row.get(3)?,
-            type_: row.get(4)?,
-            weekday_mask: row.get(5)?,
-            monthday: row.get(6)?,
-            interval_days: row.get(7)?,
-            params_json: row.get(8)?,
+/// Perfect weeks in a row, counting the running one once it is perfect.
+/// Reads the stored weeks and works out any that aren't stored yet without
+/// keeping them.
+pub async fn get_weekly_streak(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+) -> Result<i64> {
+    let today = get_day_index(clock);
+    let this_week = week_of(today, week_start);
+
+    let mut streak = 0;
+
+    // The running week is never stored, it can still change
+    let summary = summarize_week(conn, this_week, week_start, today).await?;
+    if summary.perfect() == Some(true) {
+        streak += 1;
+    }
+
+    let mut stored = load_week_stats(conn, this_week - 260 * 7).await?;
+    for check_week in finished_weeks(today, week_start) {
+        let summary = match stored.remove(&check_week) {
+            Some(summary) => summary,
+            None => summarize_week(conn, check_week, week_start, today).await?,
+        };
+        // Paused weeks are skipped without breaking the streak
+        match summary.perfect() {
+            Some(true) => streak += 1,
+            Some(false) => break,
+            None => {}
         }
-    } else {
-        return Ok(());
+    }
+
+    Ok(streak)
+}
+
+/// Re-anchors data that was bucketed into days by a different clock (UTC for
+/// databases from before day indices were zone aware) and refreshes streaks.
+///
+/// Only the days the app worked out from an instant it still has move: the
+/// first schedule version starts on the day of `created_at`, and an archived
+/// task's last version ends the day before `archived_at`. Completions,
+/// exceptions, pauses and one-off due days are dates the user picked, a new
+/// clock doesn't change which date they meant. Boundaries set by edits and
+/// unarchiving stay where they are, the instant they were made at isn't kept.
+///
+/// Returns false when the data was already on `clock`.
+pub async fn migrate_day_clock(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+) -> Result<bool> {
+    let mut rows = conn
+        .query("SELECT value FROM app_meta WHERE key = 'day_clock'", ())
+        .await?;
+    let previous = match rows.next().await? {
+        Some(row) => DayClock::parse_id(&row.get::<String>(0)?).unwrap_or_else(DayClock::utc),
+        None => DayClock::utc(),
     };
+    drop(rows);
+    if previous == *clock {
+        return Ok(false);
+    }
 
-    let mut rows_comp = conn
+    let mut rows = conn
         .query(
-            "SELECT day FROM task_completion WHERE task_id = ? ORDER BY day DESC",
-            [task_id],
+            "SELECT s.id, s.effective_from, s.effective_to, t.created_at, t.archived_at,
+                s.id = (SELECT MIN(id) FROM task_schedule WHERE task_id = s.task_id),
+                s.id = (SELECT MAX(id) FROM task_schedule WHERE task_id = s.task_id)
+            FROM task_schedule s
+            JOIN task t ON t.id = s.task_id",
+            (),
         )
         .await?;
+    let mut moves = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let schedule_id: i64 = row.get(0)?;
+        let effective_from: i64 = row.get(1)?;
+        let effective_to: Option<i64> = row.get(2)?;
+        let created_at: i64 = row.get(3)?;
+        let archived_at: Option<i64> = row.get(4)?;
+        let first: bool = row.get(5)?;
+        let last: bool = row.get(6)?;
+
+        let mut from = effective_from;
+        if first && effective_from == previous.day_of_timestamp(created_at) {
+            from = clock.day_of_timestamp(created_at);
+        }
+        let mut to = effective_to;
+        if let (true, Some(archived_at), Some(end)) = (last, archived_at, effective_to) {
+            if end == previous.day_of_timestamp(archived_at) - 1 {
+                to = Some(clock.day_of_timestamp(archived_at) - 1);
+            }
+        }
+        if (from, to) != (effective_from, effective_to) && to.is_none_or(|to| from <= to) {
+            moves.push((schedule_id, from, to));
+        }
+    }
+    drop(rows);
 
-    let mut completions = std::collections::HashSet::new();
-    while let Some(row) = rows_comp.next().await? {
-        completions.insert(row.get::<i64>(0)?);
+    let tx = conn.transaction().await?;
+    for (schedule_id, from, to) in moves {
+        tx.execute(
+            "UPDATE task_schedule SET effective_from = ?, effective_to = ? WHERE id = ?",
+            (from, to, schedule_id),
+        )
+        .await?;
     }
+    tx.execute(
+        "INSERT INTO app_meta (key, value) VALUES ('day_clock', ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        [clock.id()],
+    )
+    .await?;
+    tx.commit().await?;
 
-    let today = get_day_index();
-    let mut current_streak = 0;
+    recompute_stats(conn, clock, week_start, None).await?;
+    Ok(true)
+}
 
-    let mut loop_day = today;
-    if !completions.contains(&loop_day) {
-        loop_day -= 1;
+/// Rebuilds `task_stats` from the full completion history, for one task or
+/// every task when `task_id` is None, and the `week_stats` it covers. Run it after anything that changes what
+/// a streak is made of: settings, migrations, imported data.
+pub async fn recompute_stats(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: Option<i64>,
+) -> Result<()> {
+    let tx = conn.transaction().await?;
+    match task_id {
+        Some(_) => refresh_task_weeks(&tx, clock, week_start, task_id).await?,
+        // Everything goes, the weeks may not even start on the same day
+        None => {
+            tx.execute("DELETE FROM week_stats", ()).await?;
+            fill_weeks(&tx, clock, week_start).await?;
+        }
     }
+    rebuild_stats(&tx, clock, week_start, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
 
-    let min_day = schedule.effective_from;
-
-    while loop_day >= min_day {
-        if is_task_due(&schedule, loop_day) {
-            if completions.contains(&loop_day) {
-                current_streak += 1;
-            } else {
-                break;
+/// The `task_stats` half of `recompute_stats`, on a transaction the caller
+/// already holds. Which weeks to refresh is up to the caller.
+pub async fn rebuild_stats(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    task_id: Option<i64>,
+) -> Result<()> {
+    let task_ids = match task_id {
+        Some(task_id) => vec![task_id],
+        None => {
+            let mut rows = conn
+                .query("SELECT id FROM task WHERE deleted_at IS NULL", ())
+                .await?;
+            let mut task_ids = Vec::new();
+            while let Some(row) = rows.next().await? {
+                task_ids.push(row.get::<i64>(0)?);
             }
+            task_ids
         }
-        loop_day -= 1;
-        if today - loop_day > 1000 {
-            break;
+    };
+    for task_id in task_ids {
+        write_task_stats(conn, clock, week_start, task_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Recomputes every task once after a schema migration, stats written by an
+/// older build may have been counted differently.
+pub async fn recompute_stale_stats(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+) -> Result<()> {
+    let mut rows = conn
+        .query("SELECT value FROM app_meta WHERE key = 'stats_version'", ())
+        .await?;
+    let current = crate::migrations::LATEST_VERSION.to_string();
+    if let Some(row) = rows.next().await? {
+        if row.get::<String>(0)? == current {
+            return Ok(());
         }
     }
 
+    recompute_stats(conn, clock, week_start, None).await?;
     conn.execute(
-        "UPDATE task_stats SET current_streak = ?, best_streak = MAX(best_streak, ?) WHERE task_id = ?",
-        (current_streak, current_streak, task_id),
-    ).await?;
-
+        "INSERT INTO app_meta (key, value) VALUES ('stats_version', ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        [current],
+    )
+    .await?;
     Ok(())
 }
 
-async fn check_week_perfect(conn: &Connection, monday_day_index: i64) -> Result<bool> {
-    let start_day = monday_day_index;
-    let end_day = monday_day_index + 6;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: Weekday = Weekday::Mon;
+
+    fn version(
+        type_: &str,
+        params_json: Option<&str>,
+        from: i64,
+        to: Option<i64>,
+    ) -> ScheduleVersion {
+        ScheduleVersion::try_from(&TaskSchedule {
+            id: 1,
+            task_id: 1,
+            effective_from: from,
+            effective_to: to,
+            type_: type_.to_string(),
+            weekday_mask: (type_ == "weekly").then_some(0b000_0101),
+            monthday: None,
+            interval_days: None,
+            params_json: params_json.map(str::to_string),
+        })
+        .unwrap()
+    }
 
-    let mut rows = conn.query(
-        "SELECT s.task_id, s.effective_from, s.effective_to, s.type, s.weekday_mask, s.monthday, s.interval_days
-            FROM task_schedule s
-            WHERE s.effective_from <= ? AND (s.effective_to IS NULL OR s.effective_to >= ?)",
-        (end_day, start_day),
-    ).await?;
+    fn tally(
+        versions: &[ScheduleVersion],
+        completions: &HashMap<i64, CompletionStatus>,
+        today: i64,
+    ) -> Tally {
+        let mut marks = streak_marks(versions, completions, today, W);
+        marks.reverse();
+        Tally::count(&marks)
+    }
 
-    let mut scheds = Vec::new();
-    while let Some(row) = rows.next().await? {
-        scheds.push(TaskSchedule {
-            id: 0,
-            task_id: row.get(0)?,
-            effective_from: row.get(1)?,
-            effective_to: row.get(2)?,
-            type_: row.get(3)?,
-            weekday_mask: row.get(4)?,
-            monthday: row.get(5)?,
-            interval_days: row.get(6)?,
-            params_json: None,
-        });
+    fn done(days: impl IntoIterator<Item = i64>) -> HashMap<i64, CompletionStatus> {
+        days.into_iter()
+            .map(|day| (day, CompletionStatus::Done))
+            .collect()
     }
 
-    let mut rows_comp = conn
-        .query(
-            "SELECT task_id, day FROM task_completion WHERE day BETWEEN ? AND ?",
-            (start_day, end_day),
+    /// A Monday.
+    fn monday() -> i64 {
+        clock::date_to_day(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap())
+    }
+
+    #[test]
+    fn skipped_days_keep_the_streak() {
+        let start = monday();
+        let daily = [version("daily", None, start, None)];
+        let mut completions = done([start, start + 1, start + 3]);
+        completions.insert(start + 2, CompletionStatus::Skipped);
+        let t = tally(&daily, &completions, start + 3);
+        assert_eq!((t.current, t.best), (3, 3));
+
+        // Without the skip the gap is a miss
+        completions.remove(&(start + 2));
+        let t = tally(&daily, &completions, start + 3);
+        assert_eq!((t.current, t.best), (1, 2));
+    }
+
+    #[test]
+    fn today_is_still_open() {
+        let start = monday();
+        let daily = [version("daily", None, start, None)];
+        let completions = done(start..start + 4);
+        let t = tally(&daily, &completions, start + 4);
+        assert_eq!(t.current, 4);
+        // Once today is over an empty day breaks it
+        let t = tally(&daily, &completions, start + 5);
+        assert_eq!(t.current, 0);
+        assert_eq!(t.best, 4);
+    }
+
+    #[test]
+    fn edited_schedule_carries_the_streak() {
+        let start = monday();
+        // Daily for a week, then Mondays and Wednesdays
+        let versions = [
+            version("weekly", None, start + 7, None),
+            version("daily", None, start, Some(start + 6)),
+        ];
+        let completions = done((start..start + 7).chain([start + 7, start + 9, start + 14]));
+        let marks = streak_marks(&versions, &completions, start + 14, W);
+        assert_eq!(marks.len(), 7 + 3);
+        let t = tally(&versions, &completions, start + 14);
+        assert_eq!(t.current, 10);
+
+        // The days the weekly version doesn't cover aren't misses
+        let t = tally(&versions, &completions, start + 15);
+        assert_eq!(t.current, 10);
+    }
+
+    #[test]
+    fn best_streak_drops_after_untoggle() {
+        let start = monday();
+        let daily = [version("daily", None, start, None)];
+        let mut completions = done(start..start + 5);
+        assert_eq!(tally(&daily, &completions, start + 4).best, 5);
+        // Unticking a day in the middle is a miss now, stats are rebuilt
+        // from history rather than kept as a high-water mark
+        completions.remove(&(start + 2));
+        let t = tally(&daily, &completions, start + 4);
+        assert_eq!((t.current, t.best), (2, 2));
+    }
+
+    #[test]
+    fn quota_periods() {
+        let start = monday();
+        let quota = [version(
+            "quota",
+            Some("{\"times\":2,\"period\":\"week\"}"),
+            start,
+            None,
+        )];
+        // Week 1 met, week 2 met, week 3 one short, week 4 running
+        let completions = done([
+            start + 1,
+            start + 3,
+            start + 7,
+            start + 8,
+            start + 16,
+            start + 21,
+        ]);
+        let marks = streak_marks(&quota, &completions, start + 22, W);
+        assert_eq!(
+            marks,
+            [
+                (start + 27, Mark::Neutral),
+                (start + 20, Mark::Miss),
+                (start + 13, Mark::Hit),
+                (start + 6, Mark::Hit),
+            ]
+        );
+        // The running week already counts once it is met
+        let completions = done([start + 1, start + 3, start + 21, start + 22]);
+        assert_eq!(tally(&quota, &completions, start + 22).current, 1);
+
+        // A quota added mid-week can't fail that week
+        let late = [version(
+            "quota",
+            Some("{\"times\":3,\"period\":\"week\"}"),
+            start + 5,
+            None,
+        )];
+        let marks = streak_marks(&late, &HashMap::new(), start + 8, W);
+        assert_eq!(
+            marks,
+            [(start + 13, Mark::Neutral), (start + 6, Mark::Neutral)]
+        );
+    }
+
+    #[test]
+    fn long_history() {
+        let start = monday();
+        let daily = [version("daily", None, start, None)];
+        let completions = done(start..start + 1500);
+        let t = tally(&daily, &completions, start + 1499);
+        assert_eq!((t.current, t.best), (1500, 1500));
+        assert_eq!(t.freezes, MAX_FREEZES);
+
+        // A single miss long ago is covered by a freeze
+        let mut completions = completions;
+        completions.remove(&(start + 100));
+        let t = tally(&daily, &completions, start + 1499);
+        assert_eq!(t.frozen, [start + 100]);
+        assert_eq!(t.current, 1499);
+        // Three in a row: both held freezes go, the third miss breaks it
+        for day in start + 200..start + 203 {
+            completions.remove(&day);
+        }
+        let t = tally(&daily, &completions, start + 1499);
+        assert_eq!(t.frozen, [start + 100, start + 200, start + 201]);
+        assert_eq!((t.current, t.best), (1297, 1297));
+    }
+
+    /// Stands in for a connection dropping mid-way: every insert into
+    /// `table` fails.
+    async fn fail_inserts_into(conn: &Connection, table: &str) {
+        conn.execute(
+            &format!(
+                "CREATE TRIGGER fail_{0} BEFORE INSERT ON {0} BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+                table
+            ),
+            (),
         )
-        .await?;
-    let mut completions = std::collections::HashSet::new();
-    while let Some(row) = rows_comp.next().await? {
-        let tid: i64 = row.get(0)?;
-        let d: i64 = row.get(1)?;
-        completions.insert((tid, d));
+        .await
+        .unwrap();
+    }
+
+    async fn heal(conn: &Connection, table: &str) {
+        conn.execute(&format!("DROP TRIGGER fail_{}", table), ())
+            .await
+            .unwrap();
     }
 
-    let mut due_count = 0;
+    async fn count(conn: &Connection, table: &str) -> i64 {
+        let mut rows = conn
+            .query(&format!("SELECT COUNT(*) FROM {}", table), ())
+            .await
+            .unwrap();
+        rows.next().await.unwrap().unwrap().get(0).unwrap()
+    }
 
-    for day in start_day..=end_day {
-        for s in &scheds {
-            if is_task_due(s, day) {
-                due_count += 1;
-                if !completions.contains(&(s.task_id, day)) {
-                    return Ok(false);
+    async fn add_daily(conn: &Connection, clock: &DayClock, title: &str) -> Result<i64> {
+        add_task(
+            conn,
+            clock,
+            title.to_string(),
+            "daily".to_string(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn add_task_is_atomic() {
+        let clock = DayClock::utc();
+        for table in ["task_schedule", "task_stats"] {
+            let (_db, conn) = open_test_db().await;
+            fail_inserts_into(&conn, table).await;
+            assert!(add_daily(&conn, &clock, "water").await.is_err());
+            assert_eq!(count(&conn, "task").await, 0, "{}", table);
+            assert_eq!(count(&conn, "task_schedule").await, 0, "{}", table);
+            assert_eq!(count(&conn, "task_stats").await, 0, "{}", table);
+
+            // The connection is usable again and ids come from the insert
+            heal(&conn, table).await;
+            let first = add_daily(&conn, &clock, "water").await.unwrap();
+            let second = add_daily(&conn, &clock, "read").await.unwrap();
+            assert_ne!(first, second);
+            let tasks = list_tasks(&conn, &clock, W, None).await.unwrap();
+            let mut ids: Vec<i64> = tasks.iter().map(|t| t.task.id).collect();
+            ids.sort();
+            assert_eq!(ids, [first, second]);
+        }
+    }
+
+    #[tokio::test]
+    async fn edit_task_is_atomic() {
+        let clock = DayClock::utc();
+        // The new schedule version, and the stats that follow from it
+        for table in ["task_schedule", "task_stats"] {
+            let (_db, conn) = open_test_db().await;
+            let id = add_daily(&conn, &clock, "water").await.unwrap();
+            fail_inserts_into(&conn, table).await;
+            let edited = edit_task(
+                &conn,
+                &clock,
+                W,
+                id,
+                "drink".to_string(),
+                "weekly".to_string(),
+                Some(1),
+                None,
+                None,
+                None,
+                Some(8.0),
+                None,
+            )
+            .await;
+            assert!(edited.is_err(), "{}", table);
+
+            let tasks = list_tasks(&conn, &clock, W, None).await.unwrap();
+            assert_eq!(tasks[0].task.title, "water");
+            assert_eq!(tasks[0].task.target_amount, None);
+            assert_eq!(tasks[0].schedule.type_, "daily");
+            assert_eq!(count(&conn, "task_schedule").await, 1);
+            assert_eq!(count(&conn, "task_stats").await, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn increment_progress_is_atomic() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+        increment_progress(&conn, &clock, W, id, today, 0.5)
+            .await
+            .unwrap();
+
+        fail_inserts_into(&conn, "task_stats").await;
+        assert!(increment_progress(&conn, &clock, W, id, today, 0.5)
+            .await
+            .is_err());
+        let task = &list_tasks(&conn, &clock, W, None).await.unwrap()[0];
+        assert_eq!(task.status, CompletionStatus::InProgress);
+        assert_eq!(task.progress, Some(0.5));
+        assert_eq!(task.current_streak, 0);
+
+        heal(&conn, "task_stats").await;
+        increment_progress(&conn, &clock, W, id, today, 0.5)
+            .await
+            .unwrap();
+        let task = &list_tasks(&conn, &clock, W, None).await.unwrap()[0];
+        assert_eq!(task.status, CompletionStatus::Done);
+        assert_eq!(task.current_streak, 1);
+    }
+
+    #[tokio::test]
+    async fn marking_a_day_is_atomic() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+
+        fail_inserts_into(&conn, "task_stats").await;
+        assert!(toggle_completion(&conn, &clock, W, id, today)
+            .await
+            .is_err());
+        assert!(
+            set_completion_status(&conn, &clock, W, id, today, CompletionStatus::Skipped)
+                .await
+                .is_err()
+        );
+        assert_eq!(count(&conn, "task_completion").await, 0);
+
+        heal(&conn, "task_stats").await;
+        toggle_completion(&conn, &clock, W, id, today)
+            .await
+            .unwrap();
+        fail_inserts_into(&conn, "task_stats").await;
+        assert!(toggle_completion(&conn, &clock, W, id, today)
+            .await
+            .is_err());
+        assert!(
+            set_completion_status(&conn, &clock, W, id, today, CompletionStatus::Cleared)
+                .await
+                .is_err()
+        );
+        let task = &list_tasks(&conn, &clock, W, None).await.unwrap()[0];
+        assert_eq!(task.status, CompletionStatus::Done);
+        assert_eq!(task.current_streak, 1);
+    }
+
+    /// Monday of the running week.
+    fn this_week(clock: &DayClock) -> i64 {
+        let today = get_day_index(clock);
+        *crate::recurrence::QuotaPeriod::Week
+            .containing(today, W)
+            .start()
+    }
+
+    #[tokio::test]
+    async fn late_one_off_leaves_its_planned_week() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let this_week = this_week(&clock);
+        let planned_week = this_week - 14;
+        let due = planned_week + 2;
+        let done_on = this_week - 7 + 1;
+        let id = add_task(
+            &conn,
+            &clock,
+            "passport".to_string(),
+            "once".to_string(),
+            None,
+            None,
+            None,
+            Some(format!("{{\"due\":{}}}", get_day_index(&clock) + 1)),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ?, params_json = ? WHERE task_id = ?",
+            (planned_week, format!("{{\"due\":{}}}", due), id),
+        )
+        .await
+        .unwrap();
+
+        let today = get_day_index(&clock);
+        for week in [planned_week, planned_week + 7] {
+            let summary = summarize_week(&conn, week, W, today).await.unwrap();
+            store_week_stats(&conn, week, summary).await.unwrap();
+        }
+        let stored = load_week_stats(&conn, planned_week).await.unwrap();
+        assert_eq!(stored[&planned_week].due_count, 1);
+
+        toggle_completion(&conn, &clock, W, id, done_on)
+            .await
+            .unwrap();
+        // Whatever is still cached has to match a fresh count
+        let stored = load_week_stats(&conn, planned_week).await.unwrap();
+        for week in [planned_week, planned_week + 7] {
+            let fresh = summarize_week(&conn, week, W, today).await.unwrap();
+            assert_eq!(stored[&week], fresh, "week {}", week);
+        }
+        assert_eq!(
+            summarize_week(&conn, planned_week, W, today)
+                .await
+                .unwrap()
+                .due_count,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn weekly_streak_only_reads() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let this_week = this_week(&clock);
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+            (this_week - 14, id),
+        )
+        .await
+        .unwrap();
+        for day in this_week - 14..this_week {
+            conn.execute(
+                "INSERT INTO task_completion (task_id, day, status) VALUES (?, ?, 1)",
+                (id, day),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(get_weekly_streak(&conn, &clock, W).await.unwrap(), 2);
+        assert_eq!(count(&conn, "week_stats").await, 0);
+
+        // The next write stores the finished weeks along with its own rows
+        toggle_completion(&conn, &clock, W, id, today)
+            .await
+            .unwrap();
+        let stored = load_week_stats(&conn, 0).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[&(this_week - 14)].done_count, 7);
+        assert_eq!(get_weekly_streak(&conn, &clock, W).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_task_keeps_weeks_before_it() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let this_week = this_week(&clock);
+        let today = get_day_index(&clock);
+        let old = add_daily(&conn, &clock, "water").await.unwrap();
+        let new = add_daily(&conn, &clock, "read").await.unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+            (this_week - 28, old),
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+            (this_week - 7, new),
+        )
+        .await
+        .unwrap();
+        for day in this_week - 28..today {
+            for task_id in [old, new] {
+                set_completion_status(&conn, &clock, W, task_id, day, CompletionStatus::Done)
+                    .await
+                    .unwrap();
+            }
+        }
+        assert!(get_weekly_streak(&conn, &clock, W).await.unwrap() >= 4);
+        let before = load_week_stats(&conn, 0).await.unwrap();
+        assert_eq!(before[&(this_week - 7)].due_count, 14);
+
+        delete_task(&conn, &clock, W, new).await.unwrap();
+        let after = load_week_stats(&conn, 0).await.unwrap();
+        for week in [this_week - 28, this_week - 21, this_week - 14] {
+            assert_eq!(after.get(&week), before.get(&week), "week {}", week);
+        }
+        assert_eq!(after[&(this_week - 7)].due_count, 7);
+    }
+
+    #[tokio::test]
+    async fn quota_added_mid_week_keeps_the_weekly_streak() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let last_week = this_week(&clock) - 7;
+        let id = add_task(
+            &conn,
+            &clock,
+            "gym".to_string(),
+            "quota".to_string(),
+            None,
+            None,
+            None,
+            Some("{\"times\":3,\"period\":\"week\"}".to_string()),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let starting = |day: i64| {
+            conn.execute(
+                "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+                (day, id),
+            )
+        };
+
+        // Added on Saturday: that week can't be failed any more
+        starting(last_week + 5).await.unwrap();
+        let summary = summarize_week(&conn, last_week, W, today).await.unwrap();
+        assert_eq!(summary.due_count, 0);
+        let versions = load_versions(&conn, id, today).await.unwrap();
+        let marks = streak_marks(&versions, &HashMap::new(), today, W);
+        assert_eq!(marks.last(), Some(&(last_week + 6, Mark::Neutral)));
+
+        // Added on Monday it could have been met, and wasn't
+        starting(last_week).await.unwrap();
+        let summary = summarize_week(&conn, last_week, W, today).await.unwrap();
+        assert_eq!((summary.due_count, summary.done_count), (1, 0));
+        let versions = load_versions(&conn, id, today).await.unwrap();
+        let marks = streak_marks(&versions, &HashMap::new(), today, W);
+        assert_eq!(marks.last(), Some(&(last_week + 6, Mark::Miss)));
+    }
+
+    #[tokio::test]
+    async fn interval_edit_counts_from_the_last_completion() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "plants").await.unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+            (today - 10, id),
+        )
+        .await
+        .unwrap();
+        set_completion_status(&conn, &clock, W, id, today - 2, CompletionStatus::Done)
+            .await
+            .unwrap();
+        edit_task(
+            &conn,
+            &clock,
+            W,
+            id,
+            "plants".to_string(),
+            "custom".to_string(),
+            None,
+            None,
+            Some(4),
+            Some("{\"anchor\":\"completion\"}".to_string()),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Watered two days ago, so next due in two days rather than today
+        let versions = load_versions(&conn, id, today).await.unwrap();
+        let current = &versions[0];
+        assert_eq!(current.effective_from, today);
+        assert!(!current.is_due(today));
+        assert_eq!(current.next_after(today), Some(today + 2));
+    }
+
+    #[tokio::test]
+    async fn a_new_target_leaves_past_days_alone() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_task(
+            &conn,
+            &clock,
+            "pushups".to_string(),
+            "daily".to_string(),
+            None,
+            None,
+            None,
+            None,
+            Some(8.0),
+            None,
+        )
+        .await
+        .unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+            (today - 3, id),
+        )
+        .await
+        .unwrap();
+        // Ticked, logged past the target, and halfway there today
+        toggle_completion(&conn, &clock, W, id, today - 2)
+            .await
+            .unwrap();
+        increment_progress(&conn, &clock, W, id, today - 1, 9.0)
+            .await
+            .unwrap();
+        increment_progress(&conn, &clock, W, id, today, 6.0)
+            .await
+            .unwrap();
+        let judged = |target: f64| {
+            let conn = &conn;
+            let clock = &clock;
+            async move {
+                edit_task(
+                    conn,
+                    clock,
+                    W,
+                    id,
+                    "pushups".to_string(),
+                    "daily".to_string(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some(target),
+                    None,
+                )
+                .await
+                .unwrap();
+                let mut rows = conn
+                    .query(
+                        "SELECT status FROM task_completion WHERE task_id = ? ORDER BY day",
+                        [id],
+                    )
+                    .await
+                    .unwrap();
+                let mut statuses = Vec::new();
+                while let Some(row) = rows.next().await.unwrap() {
+                    statuses.push(row.get::<i64>(0).unwrap());
                 }
+                let mut rows = conn
+                    .query(
+                        "SELECT current_streak FROM task_stats WHERE task_id = ?",
+                        [id],
+                    )
+                    .await
+                    .unwrap();
+                let streak: i64 = rows.next().await.unwrap().unwrap().get(0).unwrap();
+                (statuses, streak)
             }
+        };
+
+        // Raising it doesn't take back days that met the old one
+        assert_eq!(judged(10.0).await, (vec![1, 1, 0], 2));
+        // Today is judged by whatever the target is now
+        assert_eq!(judged(5.0).await, (vec![1, 1, 1], 3));
+        assert_eq!(judged(20.0).await, (vec![1, 1, 0], 2));
+    }
+
+    #[tokio::test]
+    async fn progress_must_be_a_real_amount() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+        for amount in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 0.0] {
+            let result = increment_progress(&conn, &clock, W, id, today, amount).await;
+            assert!(matches!(result, Err(DbError::Invalid(_))), "{}", amount);
         }
+        assert_eq!(count(&conn, "task_completion").await, 0);
     }
 
-    Ok(due_count > 0)
-}
+    /// How many tasks the month view has due on `day`.
+    async fn due_on(conn: &Connection, clock: &DayClock, day: i64) -> i64 {
+        let date = clock::day_to_date(day);
+        // The grid only shows four weeks, late days may be on the next one
+        let next = date.with_day(1).unwrap() + chrono::Months::new(1);
+        for first in [date, next] {
+            let grid = get_month_view(conn, clock, first.year(), first.month(), W)
+                .await
+                .unwrap();
+            if let Some(view) = grid.iter().find(|view| view.day == day) {
+                return view.due_count;
+            }
+        }
+        panic!("day {} is on neither grid", day);
+    }
 
-pub async fn get_weekly_streak(conn: &Connection) -> Result<i64> {
-    let today = get_day_index();
-    let d = DateTime::from_timestamp(today * 86400, 0)
-        .unwrap()
-        .date_naive();
-    let weekday_offset = d.weekday().num_days_from_monday() as i64;
-    let this_monday = today - weekday_offset;
+    #[tokio::test]
+    async fn archiving_a_task_created_today() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+
+        archive_task(&conn, &clock, W, id).await.unwrap();
+        assert_eq!(due_on(&conn, &clock, today).await, 0);
+        assert!(list_tasks(&conn, &clock, W, None).await.unwrap().is_empty());
+        let archived = list_archived_tasks(&conn).await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].schedule.type_, "daily");
+        let mut rows = conn
+            .query(
+                "SELECT COUNT(*) FROM task_schedule WHERE effective_to < effective_from",
+                (),
+            )
+            .await
+            .unwrap();
+        let inverted: i64 = rows.next().await.unwrap().unwrap().get(0).unwrap();
+        assert_eq!(inverted, 0);
+        drop(rows);
+
+        // Back on the list from today, with the one version it had
+        unarchive_task(&conn, &clock, W, id).await.unwrap();
+        assert_eq!(due_on(&conn, &clock, today).await, 1);
+        let versions = load_versions(&conn, id, today).await.unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(
+            (versions[0].effective_from, versions[0].effective_to),
+            (today, None)
+        );
+        assert_eq!(count(&conn, "task_schedule").await, 1);
+    }
 
-    let mut streak = 0;
+    #[tokio::test]
+    async fn archiving_keeps_the_days_before() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+        conn.execute(
+            "UPDATE task_schedule SET effective_from = ? WHERE task_id = ?",
+            (today - 3, id),
+        )
+        .await
+        .unwrap();
+
+        archive_task(&conn, &clock, W, id).await.unwrap();
+        assert_eq!(due_on(&conn, &clock, today - 1).await, 1);
+        assert_eq!(due_on(&conn, &clock, today).await, 0);
+
+        unarchive_task(&conn, &clock, W, id).await.unwrap();
+        let versions = load_versions(&conn, id, today).await.unwrap();
+        let ranges: Vec<_> = versions
+            .iter()
+            .map(|v| (v.effective_from, v.effective_to))
+            .collect();
+        assert_eq!(ranges, [(today, None), (today - 3, Some(today - 1))]);
+    }
 
-    if check_week_perfect(conn, this_monday).await? {
-        streak += 1;
+    #[tokio::test]
+    async fn a_new_clock_moves_only_the_days_it_can_tell() {
+        let (_db, conn) = open_test_db().await;
+        let ts = |date: &str| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M")
+                .unwrap()
+                .and_utc()
+                .timestamp()
+        };
+        // 20:00 UTC is the next morning in Auckland
+        let created_at = ts("2024-01-10 20:00");
+        let archived_at = ts("2024-02-01 20:00");
+        let created = DayClock::utc().day_of_timestamp(created_at);
+        let archived = DayClock::utc().day_of_timestamp(archived_at);
+        for (id, archived_at) in [(1, None), (2, Some(archived_at))] {
+            conn.execute(
+                "INSERT INTO task (id, title, created_at, archived_at) VALUES (?, 'water', ?, ?)",
+                (id, created_at, archived_at),
+            )
+            .await
+            .unwrap();
+        }
+        for (task_id, from, to) in [
+            (1, created, Some(created + 9)),
+            (1, created + 10, None),
+            (2, created, Some(archived - 1)),
+        ] {
+            conn.execute(
+                "INSERT INTO task_schedule (task_id, effective_from, effective_to, type) VALUES (?, ?, ?, 'daily')",
+                (task_id, from, to),
+            )
+            .await
+            .unwrap();
+        }
+        for day in [created, created + 10] {
+            set_completion_status(&conn, &DayClock::utc(), W, 1, day, CompletionStatus::Done)
+                .await
+                .unwrap();
+        }
+
+        let auckland = DayClock::from_setting(Some("Pacific/Auckland"), 0).unwrap();
+        assert!(migrate_day_clock(&conn, &auckland, W).await.unwrap());
+        assert!(!migrate_day_clock(&conn, &auckland, W).await.unwrap());
+        let mut rows = conn
+            .query(
+                "SELECT task_id, effective_from, effective_to FROM task_schedule ORDER BY id",
+                (),
+            )
+            .await
+            .unwrap();
+        let mut versions = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            versions.push((
+                row.get::<i64>(0).unwrap(),
+                row.get::<i64>(1).unwrap(),
+                row.get::<Option<i64>>(2).unwrap(),
+            ));
+        }
+        drop(rows);
+        // Created and archived a day later, the edit stays where it was
+        assert_eq!(
+            versions,
+            [
+                (1, created + 1, Some(created + 9)),
+                (1, created + 10, None),
+                (2, created + 1, Some(archived)),
+            ]
+        );
+        let completions = load_completions(&conn, 0, created + 100).await.unwrap();
+        let mut days: Vec<i64> = completions.keys().map(|(_, day)| *day).collect();
+        days.sort();
+        assert_eq!(days, [created, created + 10]);
+    }
+
+    /// Every exception as (day, kind, paired day), days counted from `today`.
+    async fn exceptions(conn: &Connection, today: i64) -> Vec<(i64, String, Option<i64>)> {
+        let mut rows = conn
+            .query(
+                "SELECT day, kind, paired_day FROM schedule_exception ORDER BY day",
+                (),
+            )
+            .await
+            .unwrap();
+        let mut out = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            out.push((
+                row.get::<i64>(0).unwrap() - today,
+                row.get::<String>(1).unwrap(),
+                row.get::<Option<i64>>(2).unwrap().map(|day| day - today),
+            ));
+        }
+        out
     }
 
-    let mut check_monday = this_monday - 7;
-    for _ in 0..260 {
-        if check_monday < 0 {
-            break;
+    #[tokio::test]
+    async fn restoring_either_end_undoes_the_move() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+        let moved =
+            |from: i64, to: i64| move_occurrence(&conn, &clock, W, id, today + from, today + to);
+        let restored = |day: i64| restore_occurrence(&conn, &clock, W, id, today + day);
+
+        for end in [1, 3] {
+            moved(1, 3).await.unwrap();
+            assert_eq!(
+                exceptions(&conn, today).await,
+                [
+                    (1, "cancel".to_string(), Some(3)),
+                    (3, "add".to_string(), Some(1))
+                ]
+            );
+            restored(end).await.unwrap();
+            assert!(exceptions(&conn, today).await.is_empty(), "end {}", end);
         }
 
-        let perfect = check_week_perfect(conn, check_monday).await?;
-        if perfect {
-            streak += 1;
-            check_monday -= 7;
-        } else {
-            break;
+        // Moved on again it still comes from the day the rule has it on
+        moved(1, 3).await.unwrap();
+        moved(3, 5).await.unwrap();
+        assert_eq!(
+            exceptions(&conn, today).await,
+            [
+                (1, "cancel".to_string(), Some(5)),
+                (5, "add".to_string(), Some(1))
+            ]
+        );
+        restored(5).await.unwrap();
+        assert!(exceptions(&conn, today).await.is_empty());
+
+        // And moved back there it has no exception left
+        moved(1, 3).await.unwrap();
+        moved(3, 1).await.unwrap();
+        assert!(exceptions(&conn, today).await.is_empty());
+
+        // A plain cancel next to a move stays when the move is restored
+        cancel_occurrence(&conn, &clock, W, id, today + 2)
+            .await
+            .unwrap();
+        moved(1, 3).await.unwrap();
+        restored(1).await.unwrap();
+        assert_eq!(
+            exceptions(&conn, today).await,
+            [(2, "cancel".to_string(), None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn marked_days_keep_their_occurrence() {
+        let (_db, conn) = open_test_db().await;
+        let clock = DayClock::utc();
+        let today = get_day_index(&clock);
+        let id = add_daily(&conn, &clock, "water").await.unwrap();
+        set_completion_status(&conn, &clock, W, id, today + 1, CompletionStatus::Skipped)
+            .await
+            .unwrap();
+        let refused = |result: Result<()>| matches!(result, Err(DbError::Invalid(e)) if e.contains("clear it"));
+        assert!(refused(
+            cancel_occurrence(&conn, &clock, W, id, today + 1).await
+        ));
+        assert!(refused(
+            move_occurrence(&conn, &clock, W, id, today + 1, today + 2).await
+        ));
+        assert!(exceptions(&conn, today).await.is_empty());
+
+        // Nor does restoring take it off the day it was moved to
+        move_occurrence(&conn, &clock, W, id, today + 2, today + 4)
+            .await
+            .unwrap();
+        toggle_completion(&conn, &clock, W, id, today + 4)
+            .await
+            .unwrap();
+        for day in [2, 4] {
+            assert!(refused(
+                restore_occurrence(&conn, &clock, W, id, today + day).await
+            ));
         }
+        assert_eq!(exceptions(&conn, today).await.len(), 2);
+
+        // Once cleared the move can go
+        toggle_completion(&conn, &clock, W, id, today + 4)
+            .await
+            .unwrap();
+        restore_occurrence(&conn, &clock, W, id, today + 2)
+            .await
+            .unwrap();
+        assert!(exceptions(&conn, today).await.is_empty());
     }
 
-    Ok(streak)
+    #[tokio::test]
+    async fn offline_replica_behind_the_schema_waits_for_a_sync() {
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap();
+        let path = std::env::temp_dir().join(format!("talender-replica-{}.db", nanos));
+        // Nothing listens there, and the local copy never synced
+        let backend = DbBackend::Replica {
+            path: path.clone(),
+            url: "http://127.0.0.1:9".to_string(),
+            auth_token: String::new(),
+        };
+        let (_db, conn) = init_db(&backend).await.unwrap();
+        let e = check_schema(&conn).await.unwrap_err();
+        assert!(matches!(
+            e,
+            DbError::SyncRequired {
+                local: 0,
+                latest: crate::migrations::LATEST_VERSION
+            }
+        ));
+        assert!(e.to_string().starts_with("sync required: "));
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,673 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::Weekday;
+use libsql::Connection;
+
+use crate::clock::DayClock;
+use crate::db::{self, Pause, Result, Task, TaskSchedule};
+
+/// Entries kept for undo. Older ones fall off, the journal only lives for
+/// the session.
+const JOURNAL_LIMIT: usize = 100;
+
+/// The rows a command can touch. Everything in scope is read before and
+/// after the command, the difference is what undo puts back.
+#[derive(Clone, Copy, Debug)]
+pub enum Scope {
+    /// The task row with its schedule versions, exceptions, own pauses and
+    /// completions.
+    Task(i64),
+    Completion {
+        task_id: i64,
+        day: i64,
+    },
+    /// Every task row, for commands that act on all tasks at once.
+    AllTasks,
+    Pauses,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    // Parents sort before the rows that reference them
+    Task(i64),
+    Schedule(i64),
+    Completion(i64, i64),
+    Exception(i64, i64),
+    Pause(i64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CompletionRow {
+    status: i64,
+    done_at: Option<i64>,
+    value: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ExceptionRow {
+    kind: String,
+    paired_day: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Row {
+    Task(Task),
+    Schedule(TaskSchedule),
+    Completion(CompletionRow),
+    Exception(ExceptionRow),
+    Pause(Pause),
+}
+
+impl Row {
+    /// The task whose stats depend on this row, `None` for all of them.
+    fn task_id(&self, key: Key) -> Option<i64> {
+        match (self, key) {
+            (Row::Pause(pause), _) => pause.task_id,
+            (_, Key::Task(task_id))
+            | (_, Key::Completion(task_id, _))
+            | (_, Key::Exception(task_id, _)) => Some(task_id),
+            (Row::Schedule(schedule), _) => Some(schedule.task_id),
+            _ => None,
+        }
+    }
+}
+
+/// The rows in a scope at one point in time.
+pub struct Snapshot {
+    scope: Scope,
+    rows: BTreeMap<Key, Row>,
+}
+
+impl Snapshot {
+    /// A scope with nothing in it yet, e.g. a task about to be created.
+    pub fn empty(scope: Scope) -> Snapshot {
+        Snapshot {
+            scope,
+            rows: BTreeMap::new(),
+        }
+    }
+
+    pub async fn take(conn: &Connection, scope: Scope) -> Result<Snapshot> {
+        let mut rows = BTreeMap::new();
+        match scope {
+            Scope::Task(task_id) => {
+                load_tasks(conn, Some(task_id), &mut rows).await?;
+                load_schedules(conn, task_id, &mut rows).await?;
+                load_exceptions(conn, task_id, &mut rows).await?;
+                load_pauses(conn, Some(task_id), &mut rows).await?;
+                // A new target re-judges what was logged
+                load_completions(conn, task_id, None, &mut rows).await?;
+            }
+            Scope::Completion { task_id, day } => {
+                load_completions(conn, task_id, Some(day), &mut rows).await?;
+            }
+            Scope::AllTasks => load_tasks(conn, None, &mut rows).await?,
+            Scope::Pauses => load_pauses(conn, None, &mut rows).await?,
+        }
+        Ok(Snapshot { scope, rows })
+    }
+}
+
+#[derive(Debug)]
+struct Change {
+    key: Key,
+    before: Option<Row>,
+    after: Option<Row>,
+}
+
+/// One command's worth of changes.
+#[derive(Debug)]
+struct Entry {
+    changes: Vec<Change>,
+}
+
+impl Entry {
+    fn between(before: Snapshot, after: Snapshot) -> Entry {
+        let mut before = before.rows;
+        let mut changes = Vec::new();
+        for (key, row) in after.rows {
+            let previous = before.remove(&key);
+            if previous.as_ref() != Some(&row) {
+                changes.push(Change {
+                    key,
+                    before: previous,
+                    after: Some(row),
+                });
+            }
+        }
+        for (key, row) in before {
+            changes.push(Change {
+                key,
+                before: Some(row),
+                after: None,
+            });
+        }
+        changes.sort_by_key(|change| change.key);
+        Entry { changes }
+    }
+
+    /// Tasks whose stats need recomputing once the entry is applied, `None`
+    /// when a global pause is involved.
+    fn tasks(&self) -> Option<Vec<i64>> {
+        let mut tasks = Vec::new();
+        for change in &self.changes {
+            for row in [&change.before, &change.after].into_iter().flatten() {
+                tasks.push(row.task_id(change.key)?);
+            }
+        }
+        tasks.sort_unstable();
+        tasks.dedup();
+        Some(tasks)
+    }
+}
+
+/// Bounded undo and redo stacks for the session.
+#[derive(Default)]
+pub struct Journal {
+    undo: VecDeque<Entry>,
+    redo: Vec<Entry>,
+}
+
+impl Journal {
+    /// Records what changed in `before`'s scope since it was taken. A new
+    /// change drops whatever could have been redone.
+    pub async fn record(&mut self, conn: &Connection, before: Snapshot) -> Result<()> {
+        let after = Snapshot::take(conn, before.scope).await?;
+        let entry = Entry::between(before, after);
+        if entry.changes.is_empty() {
+            return Ok(());
+        }
+        self.redo.clear();
+        self.undo.push_back(entry);
+        if self.undo.len() > JOURNAL_LIMIT {
+            self.undo.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Forgets everything, for changes made around the journal. Undoing an
+    /// entry recorded before one of them would put back rows it has moved.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Reverts the latest change. Returns false when there is nothing to undo.
+    pub async fn undo(
+        &mut self,
+        conn: &Connection,
+        clock: &DayClock,
+        week_start: Weekday,
+    ) -> Result<bool> {
+        let Some(entry) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+        match apply(conn, clock, week_start, &entry, Side::Before).await {
+            Ok(()) => {
+                self.redo.push(entry);
+                Ok(true)
+            }
+            Err(e) => {
+                self.undo.push_back(entry);
+                Err(e)
+            }
+        }
+    }
+
+    /// Applies the latest undone change again. Returns false when there is
+    /// nothing to redo.
+    pub async fn redo(
+        &mut self,
+        conn: &Connection,
+        clock: &DayClock,
+        week_start: Weekday,
+    ) -> Result<bool> {
+        let Some(entry) = self.redo.pop() else {
+            return Ok(false);
+        };
+        match apply(conn, clock, week_start, &entry, Side::After).await {
+            Ok(()) => {
+                self.undo.push_back(entry);
+                Ok(true)
+            }
+            Err(e) => {
+                self.redo.push(entry);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Before,
+    After,
+}
+
+/// Writes one side of an entry and recomputes the stats that depend on it,
+/// all in one transaction.
+async fn apply(
+    conn: &Connection,
+    clock: &DayClock,
+    week_start: Weekday,
+    entry: &Entry,
+    side: Side,
+) -> Result<()> {
+    let image = |change: &Change| match side {
+        Side::Before => change.before.clone(),
+        Side::After => change.after.clone(),
+    };
+
+    let tx = conn.transaction().await?;
+    // Children go before their parents, parents come back before children
+    for change in entry.changes.iter().rev() {
+        if image(change).is_none() {
+            delete_row(&tx, change.key).await?;
+        }
+    }
+    for change in &entry.changes {
+        if let Some(row) = image(change) {
+            write_row(&tx, change.key, &row).await?;
+        }
+    }
+    if let Some(from) = counts_from(&tx, entry).await? {
+        db::refresh_weeks(&tx, clock, week_start, from).await?;
+    }
+    match entry.tasks() {
+        Some(tasks) => {
+            for task_id in tasks {
+                db::rebuild_stats(&tx, clock, week_start, Some(task_id)).await?;
+            }
+        }
+        None => db::rebuild_stats(&tx, clock, week_start, None).await?,
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The first day whose week the rows of `entry` count towards.
+async fn counts_from(conn: &Connection, entry: &Entry) -> Result<Option<i64>> {
+    let mut from: Option<i64> = None;
+    let mut earliest = |day: i64| from = Some(from.map_or(day, |from| from.min(day)));
+    for change in &entry.changes {
+        match change.key {
+            Key::Task(task_id) => {
+                if let Some(day) = db::task_counts_from(conn, Some(task_id)).await? {
+                    earliest(day);
+                }
+            }
+            Key::Completion(task_id, day) => {
+                earliest(db::completion_counts_from(conn, task_id, day).await?)
+            }
+            Key::Exception(_, day) => earliest(day),
+            Key::Schedule(_) | Key::Pause(_) => {
+                for row in [&change.before, &change.after].into_iter().flatten() {
+                    match row {
+                        Row::Schedule(schedule) => earliest(schedule.effective_from),
+                        Row::Pause(pause) => earliest(pause.start_day),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    Ok(from)
+}
+
+async fn delete_row(conn: &Connection, key: Key) -> Result<()> {
+    match key {
+        Key::Task(id) => conn.execute("DELETE FROM task WHERE id = ?", [id]).await?,
+        Key::Schedule(id) => {
+            conn.execute("DELETE FROM task_schedule WHERE id = ?", [id])
+                .await?
+        }
+        Key::Completion(task_id, day) => {
+            conn.execute(
+                "DELETE FROM task_completion WHERE task_id = ? AND day = ?",
+                (task_id, day),
+            )
+            .await?
+        }
+        Key::Exception(task_id, day) => {
+            conn.execute(
+                "DELETE FROM schedule_exception WHERE task_id = ? AND day = ?",
+                (task_id, day),
+            )
+            .await?
+        }
+        Key::Pause(id) => conn.execute("DELETE FROM pause WHERE id = ?", [id]).await?,
+    };
+    Ok(())
+}
+
+// Upserts rather than INSERT OR REPLACE, a replace deletes the old row first
+// and that would cascade
+async fn write_row(conn: &Connection, key: Key, row: &Row) -> Result<()> {
+    match (key, row) {
+        (_, Row::Task(task)) => {
+            conn.execute(
+                "INSERT INTO task (id, title, notes, is_active, created_at, archived_at, target_amount, target_unit, deleted_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT(id) DO UPDATE SET
+                        title = excluded.title,
+                        notes = excluded.notes,
+                        is_active = excluded.is_active,
+                        created_at = excluded.created_at,
+                        archived_at = excluded.archived_at,
+                        target_amount = excluded.target_amount,
+                        target_unit = excluded.target_unit,
+                        deleted_at = excluded.deleted_at",
+                (
+                    task.id,
+                    task.title.clone(),
+                    task.notes.clone(),
+                    task.is_active,
+                    task.created_at,
+                    task.archived_at,
+                    task.target_amount,
+                    task.target_unit.clone(),
+                    task.deleted_at,
+                ),
+            )
+            .await?;
+        }
+        (_, Row::Schedule(schedule)) => {
+            conn.execute(
+                "INSERT INTO task_schedule (id, task_id, effective_from, effective_to, type, weekday_mask, monthday, interval_days, params_json)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT(id) DO UPDATE SET
+                        effective_from = excluded.effective_from,
+                        effective_to = excluded.effective_to,
+                        type = excluded.type,
+                        weekday_mask = excluded.weekday_mask,
+                        monthday = excluded.monthday,
+                        interval_days = excluded.interval_days,
+                        params_json = excluded.params_json",
+                (
+                    schedule.id,
+                    schedule.task_id,
+                    schedule.effective_from,
+                    schedule.effective_to,
+                    schedule.type_.clone(),
+                    schedule.weekday_mask,
+                    schedule.monthday,
+                    schedule.interval_days,
+                    schedule.params_json.clone(),
+                ),
+            )
+            .await?;
+        }
+        (Key::Completion(task_id, day), Row::Completion(completion)) => {
+            conn.execute(
+                "INSERT INTO task_completion (task_id, day, status, done_at, value) VALUES (?, ?, ?, ?, ?)
+                    ON CONFLICT(task_id, day) DO UPDATE SET
+                        status = excluded.status,
+                        done_at = excluded.done_at,
+                        value = excluded.value",
+                (
+                    task_id,
+                    day,
+                    completion.status,
+                    completion.done_at,
+                    completion.value,
+                ),
+            )
+            .await?;
+        }
+        (Key::Exception(task_id, day), Row::Exception(exception)) => {
+            conn.execute(
+                "INSERT INTO schedule_exception (task_id, day, kind, paired_day) VALUES (?, ?, ?, ?)
+                    ON CONFLICT(task_id, day) DO UPDATE SET
+                        kind = excluded.kind,
+                        paired_day = excluded.paired_day",
+                (task_id, day, exception.kind.clone(), exception.paired_day),
+            )
+            .await?;
+        }
+        (_, Row::Pause(pause)) => {
+            conn.execute(
+                "INSERT INTO pause (id, task_id, start_day, end_day) VALUES (?, ?, ?, ?)
+                    ON CONFLICT(id) DO UPDATE SET
+                        task_id = excluded.task_id,
+                        start_day = excluded.start_day,
+                        end_day = excluded.end_day",
+                (pause.id, pause.task_id, pause.start_day, pause.end_day),
+            )
+            .await?;
+        }
+        (key, row) => unreachable!("{:?} can't hold {:?}", key, row),
+    }
+    Ok(())
+}
+
+async fn load_tasks(
+    conn: &Connection,
+    task_id: Option<i64>,
+    out: &mut BTreeMap<Key, Row>,
+) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT id, title, notes, is_active, created_at, archived_at, target_amount, target_unit, deleted_at
+                FROM task WHERE ?1 IS NULL OR id = ?1",
+            [task_id],
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        let task = Task {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            notes: row.get(2)?,
+            is_active: row.get(3)?,
+            created_at: row.get(4)?,
+            archived_at: row.get(5)?,
+            target_amount: row.get(6)?,
+            target_unit: row.get(7)?,
+            deleted_at: row.get(8)?,
+        };
+        out.insert(Key::Task(task.id), Row::Task(task));
+    }
+    Ok(())
+}
+
+async fn load_schedules(
+    conn: &Connection,
+    task_id: i64,
+    out: &mut BTreeMap<Key, Row>,
+) -> Result<()> {
+    let mut rows = conn
+        .query("SELECT * FROM task_schedule WHERE task_id = ?", [task_id])
+        .await?;
+    while let Some(row) = rows.next().await? {
+        let schedule = TaskSchedule::from_row(&row, 0)?;
+        out.insert(Key::Schedule(schedule.id), Row::Schedule(schedule));
+    }
+    Ok(())
+}
+
+/// One day of `task_id`, or all of them when `day` is None.
+async fn load_completions(
+    conn: &Connection,
+    task_id: i64,
+    day: Option<i64>,
+    out: &mut BTreeMap<Key, Row>,
+) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT day, status, done_at, value FROM task_completion WHERE task_id = ?1 AND (?2 IS NULL OR day = ?2)",
+            (task_id, day),
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        let day: i64 = row.get(0)?;
+        let completion = CompletionRow {
+            status: row.get(1)?,
+            done_at: row.get(2)?,
+            value: row.get(3)?,
+        };
+        out.insert(Key::Completion(task_id, day), Row::Completion(completion));
+    }
+    Ok(())
+}
+
+async fn load_exceptions(
+    conn: &Connection,
+    task_id: i64,
+    out: &mut BTreeMap<Key, Row>,
+) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT day, kind, paired_day FROM schedule_exception WHERE task_id = ?",
+            [task_id],
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        let day: i64 = row.get(0)?;
+        let exception = ExceptionRow {
+            kind: row.get(1)?,
+            paired_day: row.get(2)?,
+        };
+        out.insert(Key::Exception(task_id, day), Row::Exception(exception));
+    }
+    Ok(())
+}
+
+async fn load_pauses(
+    conn: &Connection,
+    task_id: Option<i64>,
+    out: &mut BTreeMap<Key, Row>,
+) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT id, task_id, start_day, end_day FROM pause WHERE ?1 IS NULL OR task_id = ?1",
+            [task_id],
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        let pause = Pause {
+            id: row.get(0)?,
+            task_id: row.get(1)?,
+            start_day: row.get(2)?,
+            end_day: row.get(3)?,
+        };
+        out.insert(Key::Pause(pause.id), Row::Pause(pause));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::get_day_index;
+
+    #[tokio::test]
+    async fn failed_undo_changes_nothing() {
+        let (_db, conn) = db::open_test_db().await;
+        let clock = DayClock::utc();
+        let week_start = Weekday::Mon;
+        let today = get_day_index(&clock);
+        let task_id = db::add_task(
+            &conn,
+            &clock,
+            "water".to_string(),
+            "daily".to_string(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let mut journal = Journal::default();
+        let scope = Scope::Completion {
+            task_id,
+            day: today,
+        };
+        let before = Snapshot::take(&conn, scope).await.unwrap();
+        db::toggle_completion(&conn, &clock, week_start, task_id, today)
+            .await
+            .unwrap();
+        journal.record(&conn, before).await.unwrap();
+
+        // The stats recompute fails after the rows were already written back
+        conn.execute(
+            "CREATE TRIGGER fail_stats BEFORE INSERT ON task_stats BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+            (),
+        )
+        .await
+        .unwrap();
+        assert!(journal.undo(&conn, &clock, week_start).await.is_err());
+        let task = &db::list_tasks(&conn, &clock, week_start, None)
+            .await
+            .unwrap()[0];
+        assert!(task.today_status);
+        assert_eq!(task.current_streak, 1);
+
+        // Still on the stack, so trying again once it works undoes it
+        conn.execute("DROP TRIGGER fail_stats", ()).await.unwrap();
+        assert!(journal.undo(&conn, &clock, week_start).await.unwrap());
+        let task = &db::list_tasks(&conn, &clock, week_start, None)
+            .await
+            .unwrap()[0];
+        assert!(!task.today_status);
+        assert_eq!(task.current_streak, 0);
+        assert!(!journal.undo(&conn, &clock, week_start).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn undoing_a_new_target_restores_the_statuses() {
+        let (_db, conn) = db::open_test_db().await;
+        let clock = DayClock::utc();
+        let week_start = Weekday::Mon;
+        let today = get_day_index(&clock);
+        let task_id = db::add_task(
+            &conn,
+            &clock,
+            "pushups".to_string(),
+            "daily".to_string(),
+            None,
+            None,
+            None,
+            None,
+            Some(10.0),
+            None,
+        )
+        .await
+        .unwrap();
+        db::increment_progress(&conn, &clock, week_start, task_id, today, 6.0)
+            .await
+            .unwrap();
+        let today_status = || async {
+            db::list_tasks(&conn, &clock, week_start, None)
+                .await
+                .unwrap()[0]
+                .today_status
+        };
+
+        let mut journal = Journal::default();
+        let before = Snapshot::take(&conn, Scope::Task(task_id)).await.unwrap();
+        db::edit_task(
+            &conn,
+            &clock,
+            week_start,
+            task_id,
+            "pushups".to_string(),
+            "daily".to_string(),
+            None,
+            None,
+            None,
+            None,
+            Some(5.0),
+            None,
+        )
+        .await
+        .unwrap();
+        journal.record(&conn, before).await.unwrap();
+        assert!(today_status().await);
+
+        assert!(journal.undo(&conn, &clock, week_start).await.unwrap());
+        assert!(!today_status().await);
+    }
+}
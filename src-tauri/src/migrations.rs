@@ -0,0 +1,413 @@
+use libsql::{Connection, Error, Result};
+
+/// Schema steps in order. Applying `MIGRATIONS[n]` brings the database to
+/// version `n + 1`. Never edit a step that has shipped, append a new one.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_initial.sql"),
+    include_str!("../migrations/0002_app_meta.sql"),
+    include_str!("../migrations/0003_quantitative_targets.sql"),
+    include_str!("../migrations/0004_schedule_checks.sql"),
+    include_str!("../migrations/0005_pauses.sql"),
+    include_str!("../migrations/0006_schedule_exceptions.sql"),
+    include_str!("../migrations/0007_week_stats_paused.sql"),
+    include_str!("../migrations/0008_streak_freezes.sql"),
+    include_str!("../migrations/0009_trash.sql"),
+    include_str!("../migrations/0010_exception_pairs.sql"),
+];
+
+pub const LATEST_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Brings the schema up to `LATEST_VERSION`, one transaction per step.
+/// Databases written by a newer build are refused rather than guessed at.
+pub async fn run(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version     INTEGER PRIMARY KEY,
+            applied_at  INTEGER NOT NULL DEFAULT (unixepoch())
+        )",
+        (),
+    )
+    .await?;
+
+    let current = current_version(conn).await?;
+    if current > LATEST_VERSION {
+        return Err(Error::ConnectionFailed(format!(
+            "database schema is at version {} but this build only knows up to {}, please update talender",
+            current, LATEST_VERSION
+        )));
+    }
+
+    for (i, step) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = i as i64 + 1;
+        let tx = conn.transaction().await?;
+        tx.execute_batch(step).await?;
+        tx.execute("INSERT INTO schema_version (version) VALUES (?)", [version])
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+pub async fn current_version(conn: &Connection) -> Result<i64> {
+    let mut rows = conn
+        .query("SELECT COALESCE(MAX(version), 0) FROM schema_version", ())
+        .await?;
+    match rows.next().await? {
+        Some(row) => row.get(0),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsql::Builder;
+
+    /// An in-memory database with the first `version` steps applied, the way
+    /// an older build left it.
+    async fn at_version(version: usize) -> Connection {
+        let db = Builder::new_local(":memory:").build().await.unwrap();
+        let conn = db.connect().unwrap();
+        conn.execute("PRAGMA foreign_keys = ON", ()).await.unwrap();
+        conn.execute(
+            "CREATE TABLE schema_version (
+                version     INTEGER PRIMARY KEY,
+                applied_at  INTEGER NOT NULL DEFAULT (unixepoch())
+            )",
+            (),
+        )
+        .await
+        .unwrap();
+        for (i, step) in MIGRATIONS[..version].iter().enumerate() {
+            conn.execute_batch(step).await.unwrap();
+            conn.execute(
+                "INSERT INTO schema_version (version) VALUES (?)",
+                [i as i64 + 1],
+            )
+            .await
+            .unwrap();
+        }
+        conn
+    }
+
+    async fn int(conn: &Connection, sql: &str) -> Option<i64> {
+        let mut rows = conn.query(sql, ()).await.unwrap();
+        rows.next().await.unwrap().unwrap().get(0).unwrap()
+    }
+
+    async fn seed_task(conn: &Connection) {
+        conn.execute("INSERT INTO task (id, title) VALUES (1, 'water')", ())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fresh_database_reaches_latest() {
+        let conn = at_version(0).await;
+        run(&conn).await.unwrap();
+        assert_eq!(current_version(&conn).await.unwrap(), LATEST_VERSION);
+        // A second run has nothing left to do
+        run(&conn).await.unwrap();
+        assert_eq!(
+            int(&conn, "SELECT COUNT(*) FROM schema_version").await,
+            Some(LATEST_VERSION)
+        );
+    }
+
+    #[tokio::test]
+    async fn app_meta_from_1() {
+        let conn = at_version(1).await;
+        seed_task(&conn).await;
+        run(&conn).await.unwrap();
+        conn.execute("INSERT INTO app_meta (key, value) VALUES ('k', 'v')", ())
+            .await
+            .unwrap();
+        assert!(conn
+            .execute("INSERT INTO app_meta (key, value) VALUES ('k', 'w')", ())
+            .await
+            .is_err());
+        assert_eq!(int(&conn, "SELECT COUNT(*) FROM task").await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn quantitative_targets_from_2() {
+        let conn = at_version(2).await;
+        seed_task(&conn).await;
+        conn.execute(
+            "INSERT INTO task_completion (task_id, day, status, done_at) VALUES (1, 100, 1, 5), (1, 101, 2, 6)",
+            (),
+        )
+        .await
+        .unwrap();
+        // In-progress days didn't exist yet
+        assert!(conn
+            .execute(
+                "INSERT INTO task_completion (task_id, day, status) VALUES (1, 102, 0)",
+                ()
+            )
+            .await
+            .is_err());
+
+        run(&conn).await.unwrap();
+
+        assert_eq!(
+            int(&conn, "SELECT SUM(status) FROM task_completion WHERE value IS NULL AND done_at IS NOT NULL").await,
+            Some(3)
+        );
+        conn.execute(
+            "INSERT INTO task_completion (task_id, day, status, value) VALUES (1, 102, 0, 2.5)",
+            (),
+        )
+        .await
+        .unwrap();
+        assert!(conn
+            .execute(
+                "INSERT INTO task_completion (task_id, day, status) VALUES (1, 103, 3)",
+                ()
+            )
+            .await
+            .is_err());
+        conn.execute(
+            "UPDATE task SET target_amount = 8, target_unit = 'glasses'",
+            (),
+        )
+        .await
+        .unwrap();
+        // The rebuilt table still cascades
+        conn.execute("DELETE FROM task", ()).await.unwrap();
+        assert_eq!(
+            int(&conn, "SELECT COUNT(*) FROM task_completion").await,
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn schedule_checks_from_3() {
+        let conn = at_version(3).await;
+        seed_task(&conn).await;
+        conn.execute(
+            "INSERT INTO task_schedule (id, task_id, effective_from, type, weekday_mask, monthday)
+                VALUES (7, 1, 100, 'weekly', 5, NULL), (8, 1, 90, 'monthly', NULL, 28)",
+            (),
+        )
+        .await
+        .unwrap();
+        assert!(conn
+            .execute(
+                "INSERT INTO task_schedule (task_id, effective_from, type) VALUES (1, 100, 'rrule')",
+                (),
+            )
+            .await
+            .is_err());
+
+        run(&conn).await.unwrap();
+
+        assert_eq!(
+            int(&conn, "SELECT weekday_mask FROM task_schedule WHERE id = 7").await,
+            Some(5)
+        );
+        assert_eq!(
+            int(&conn, "SELECT monthday FROM task_schedule WHERE id = 8").await,
+            Some(28)
+        );
+        for (type_, monthday) in [
+            ("rrule", None),
+            ("monthly", Some(31)),
+            ("monthly", Some(-1)),
+        ] {
+            conn.execute(
+                "INSERT INTO task_schedule (task_id, effective_from, type, monthday) VALUES (1, 100, ?, ?)",
+                (type_, monthday),
+            )
+            .await
+            .unwrap();
+        }
+        for monthday in [0, 32, -2] {
+            assert!(conn
+                .execute(
+                    "INSERT INTO task_schedule (task_id, effective_from, type, monthday) VALUES (1, 100, 'monthly', ?)",
+                    [monthday],
+                )
+                .await
+                .is_err());
+        }
+        // Ids keep counting from where they were
+        conn.execute(
+            "INSERT INTO task_schedule (task_id, effective_from, type) VALUES (1, 100, 'daily')",
+            (),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            int(&conn, "SELECT MAX(id) FROM task_schedule").await,
+            Some(12)
+        );
+        conn.execute("DELETE FROM task", ()).await.unwrap();
+        assert_eq!(
+            int(&conn, "SELECT COUNT(*) FROM task_schedule").await,
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn pauses_from_4() {
+        let conn = at_version(4).await;
+        seed_task(&conn).await;
+        run(&conn).await.unwrap();
+        conn.execute(
+            "INSERT INTO pause (task_id, start_day, end_day) VALUES (NULL, 10, NULL), (1, 10, 12)",
+            (),
+        )
+        .await
+        .unwrap();
+        assert!(conn
+            .execute(
+                "INSERT INTO pause (task_id, start_day, end_day) VALUES (1, 10, 9)",
+                ()
+            )
+            .await
+            .is_err());
+        conn.execute("DELETE FROM task", ()).await.unwrap();
+        assert_eq!(int(&conn, "SELECT COUNT(*) FROM pause").await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn schedule_exceptions_from_5() {
+        let conn = at_version(5).await;
+        seed_task(&conn).await;
+        run(&conn).await.unwrap();
+        conn.execute(
+            "INSERT INTO schedule_exception (task_id, day, kind) VALUES (1, 10, 'cancel'), (1, 11, 'add')",
+            (),
+        )
+        .await
+        .unwrap();
+        assert!(conn
+            .execute(
+                "INSERT INTO schedule_exception (task_id, day, kind) VALUES (1, 12, 'move')",
+                ()
+            )
+            .await
+            .is_err());
+        assert!(conn
+            .execute(
+                "INSERT INTO schedule_exception (task_id, day, kind) VALUES (1, 10, 'add')",
+                ()
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn week_stats_paused_from_6() {
+        let conn = at_version(6).await;
+        conn.execute(
+            "INSERT INTO week_stats (week_start_day, all_done, due_count, done_count) VALUES (7, 1, 3, 3)",
+            (),
+        )
+        .await
+        .unwrap();
+        run(&conn).await.unwrap();
+        assert_eq!(int(&conn, "SELECT COUNT(*) FROM week_stats").await, Some(0));
+        conn.execute("INSERT INTO week_stats (week_start_day) VALUES (14)", ())
+            .await
+            .unwrap();
+        assert_eq!(int(&conn, "SELECT paused FROM week_stats").await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn streak_freezes_from_7() {
+        let conn = at_version(7).await;
+        seed_task(&conn).await;
+        conn.execute(
+            "INSERT INTO task_stats (task_id, current_streak, best_streak) VALUES (1, 4, 9)",
+            (),
+        )
+        .await
+        .unwrap();
+        run(&conn).await.unwrap();
+        assert_eq!(
+            int(&conn, "SELECT best_streak * 10 + freezes FROM task_stats").await,
+            Some(90)
+        );
+        conn.execute(
+            "INSERT INTO streak_freeze (task_id, day) VALUES (1, 10)",
+            (),
+        )
+        .await
+        .unwrap();
+        assert!(conn
+            .execute(
+                "INSERT INTO streak_freeze (task_id, day) VALUES (2, 10)",
+                ()
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn trash_from_8() {
+        let conn = at_version(8).await;
+        seed_task(&conn).await;
+        run(&conn).await.unwrap();
+        assert_eq!(
+            int(&conn, "SELECT deleted_at FROM task WHERE id = 1").await,
+            None
+        );
+        conn.execute("UPDATE task SET deleted_at = 5", ())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn exception_pairs_from_9() {
+        let conn = at_version(9).await;
+        seed_task(&conn).await;
+        // A move, and a cancel and an add made apart
+        conn.execute(
+            "INSERT INTO schedule_exception (task_id, day, kind, created_at) VALUES
+                (1, 10, 'cancel', 100), (1, 12, 'add', 100),
+                (1, 20, 'cancel', 200), (1, 30, 'add', 300)",
+            (),
+        )
+        .await
+        .unwrap();
+        run(&conn).await.unwrap();
+        let paired = |day: i64| {
+            let conn = &conn;
+            async move {
+                int(
+                    conn,
+                    &format!(
+                        "SELECT paired_day FROM schedule_exception WHERE day = {}",
+                        day
+                    ),
+                )
+                .await
+            }
+        };
+        assert_eq!(paired(10).await, Some(12));
+        assert_eq!(paired(12).await, Some(10));
+        assert_eq!(paired(20).await, None);
+        assert_eq!(paired(30).await, None);
+    }
+
+    #[tokio::test]
+    async fn newer_schema_is_refused() {
+        let conn = at_version(MIGRATIONS.len()).await;
+        conn.execute(
+            "INSERT INTO schema_version (version) VALUES (?)",
+            [LATEST_VERSION + 1],
+        )
+        .await
+        .unwrap();
+        let err = run(&conn).await.unwrap_err();
+        assert!(
+            err.to_string().contains("please update talender"),
+            "{}",
+            err
+        );
+        assert_eq!(current_version(&conn).await.unwrap(), LATEST_VERSION + 1);
+    }
+}
@@ -0,0 +1,991 @@
+//! The one place that decides whether a task is due on a given day. Month
+//! view, streaks, weekly checks and the task list all go through here.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+
+use crate::clock;
+use crate::db::TaskSchedule;
+use crate::rrule::{self, RRule};
+
+/// How far `next_after` looks ahead before giving up.
+const LOOKAHEAD_DAYS: i64 = 400 * 366;
+
+/// `task_schedule.monthday` value for "last day of the month".
+pub const LAST_DAY_OF_MONTH: i64 = -1;
+
+/// Settings that don't have a column of their own, stored as JSON in
+/// `task_schedule.params_json`.
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct ScheduleParams {
+    /// RFC 5545 rule for the "rrule" type, e.g. `FREQ=MONTHLY;BYDAY=2TU`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rrule: Option<String>,
+    /// What a monthly schedule on the 29th-31st does in months that are too short.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub short_month: Option<ShortMonth>,
+    /// Which weekday of the month a "monthly_nth" schedule falls on: 1 is the
+    /// first, -1 the last.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nth: Option<i64>,
+    /// Month of a "yearly" schedule, 1 is January.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub month: Option<u32>,
+    /// Completions a "quota" schedule needs per `period`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub times: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<QuotaPeriod>,
+    /// Day index a "once" task is planned for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due: Option<i64>,
+    /// Last day a "once" task may be done on before it counts as overdue,
+    /// defaults to `due`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<i64>,
+    /// What the interval of a "custom" schedule counts from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<IntervalAnchor>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IntervalAnchor {
+    /// Fixed grid from the day the schedule starts.
+    #[default]
+    Schedule,
+    /// The last time the task was done (or skipped).
+    Completion,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortMonth {
+    /// Fall back to the last day of the month.
+    #[default]
+    Clamp,
+    /// No occurrence that month.
+    Skip,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    Week,
+    Month,
+}
+
+impl QuotaPeriod {
+    /// The week (starting on `week_start`) or calendar month `day` falls in.
+    pub fn containing(self, day: i64, week_start: Weekday) -> RangeInclusive<i64> {
+        let date = clock::day_to_date(day);
+        match self {
+            QuotaPeriod::Week => {
+                let start = day - date.weekday().days_since(week_start) as i64;
+                start..=start + 6
+            }
+            QuotaPeriod::Month => {
+                let start = day - date.day0() as i64;
+                start..=start + rrule::days_in_month(date) as i64 - 1
+            }
+        }
+    }
+
+    fn length(self) -> i64 {
+        match self {
+            QuotaPeriod::Week => 7,
+            QuotaPeriod::Month => 31,
+        }
+    }
+}
+
+/// A one day override of what the pattern says, from `schedule_exception`.
+/// Moving an occurrence is a cancel on the old day plus an add on the new.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    Cancel,
+    Add,
+}
+
+impl Exception {
+    pub fn from_db(kind: &str) -> Option<Exception> {
+        match kind {
+            "cancel" => Some(Exception::Cancel),
+            "add" => Some(Exception::Add),
+            _ => None,
+        }
+    }
+
+    pub fn as_db(self) -> &'static str {
+        match self {
+            Exception::Cancel => "cancel",
+            Exception::Add => "add",
+        }
+    }
+}
+
+impl ScheduleParams {
+    pub fn parse(params_json: Option<&str>) -> Result<ScheduleParams, String> {
+        match params_json {
+            Some(json) if !json.trim().is_empty() => {
+                serde_json::from_str(json).map_err(|e| format!("invalid schedule params: {}", e))
+            }
+            _ => Ok(ScheduleParams::default()),
+        }
+    }
+}
+
+/// The repeating pattern of a schedule, anchored where it needs an anchor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recurrence {
+    Daily,
+    /// Bit 0 is Monday, bit 6 Sunday.
+    Weekly {
+        weekday_mask: i64,
+    },
+    /// `monthday` is 1..=31 or `LAST_DAY_OF_MONTH`.
+    Monthly {
+        monthday: i64,
+        short_month: ShortMonth,
+    },
+    /// "monthly_nth": the `nth` `weekday` of every month, counted from the
+    /// end for negative `nth`. Months without that many are skipped.
+    NthWeekday {
+        nth: i64,
+        weekday: Weekday,
+    },
+    /// `monthday` of `month` every year, with the same short month handling
+    /// as `Monthly` (only matters for February 29th).
+    Yearly {
+        month: u32,
+        monthday: i64,
+        short_month: ShortMonth,
+    },
+    /// "quota": `times` completions on any days of each `period`. Every day
+    /// matches, whether the period is met depends on the logged completions.
+    Quota {
+        times: i64,
+        period: QuotaPeriod,
+    },
+    /// "custom" anchored to completions: due `interval` days after it was
+    /// last done and every day after that until it is done again. Needs a
+    /// `History` on the version, without one it projects from `start`.
+    AfterCompletion {
+        interval: i64,
+        start: i64,
+    },
+    /// "once": a one-off planned for `due`. With a `History` it moves to the
+    /// day it was done on, and shows up today as well while it is late.
+    Once {
+        due: i64,
+        deadline: i64,
+    },
+    /// The "custom" type: every `interval` days counted from `anchor`.
+    EveryNDays {
+        interval: i64,
+        anchor: i64,
+    },
+    /// RFC 5545 rule with DTSTART `start`, see `Recurrence::rule_end`.
+    Rule {
+        rule: RRule,
+        start: i64,
+        end: RuleEnd,
+    },
+}
+
+/// Last day a rule can fire on, only worked out once something asks: finding
+/// the COUNT-th occurrence can mean walking through centuries of days.
+#[derive(Clone, Debug, Default)]
+pub struct RuleEnd(OnceLock<Option<i64>>);
+
+impl PartialEq for RuleEnd {
+    // Follows from the rule and its start, which are compared already
+    fn eq(&self, _: &RuleEnd) -> bool {
+        true
+    }
+}
+
+impl Recurrence {
+    /// Builds the pattern for a schedule row starting on `effective_from`.
+    pub fn parse(
+        type_: &str,
+        weekday_mask: Option<i64>,
+        monthday: Option<i64>,
+        interval_days: Option<i64>,
+        params_json: Option<&str>,
+        effective_from: i64,
+    ) -> Result<Recurrence, String> {
+        let params = ScheduleParams::parse(params_json)?;
+        match type_ {
+            "daily" => Ok(Recurrence::Daily),
+            "weekly" => match weekday_mask {
+                Some(mask) if (1..=0b111_1111).contains(&mask) => {
+                    Ok(Recurrence::Weekly { weekday_mask: mask })
+                }
+                _ => Err("weekly schedules need at least one weekday".to_string()),
+            },
+            "monthly" => match monthday {
+                Some(day) if day == LAST_DAY_OF_MONTH || (1..=31).contains(&day) => {
+                    Ok(Recurrence::Monthly {
+                        monthday: day,
+                        short_month: params.short_month.unwrap_or_default(),
+                    })
+                }
+                _ => Err(format!(
+                    "monthly schedules need a day of the month between 1 and 31, or {} for the last day",
+                    LAST_DAY_OF_MONTH
+                )),
+            },
+            "monthly_nth" => {
+                let weekday = match weekday_mask {
+                    Some(mask) if (1..=0b111_1111).contains(&mask) && mask.count_ones() == 1 => {
+                        Weekday::try_from(mask.trailing_zeros() as u8).map_err(|e| e.to_string())?
+                    }
+                    _ => return Err("monthly_nth schedules need exactly one weekday".to_string()),
+                };
+                match params.nth {
+                    Some(nth) if nth != 0 && (-5..=5).contains(&nth) => {
+                        Ok(Recurrence::NthWeekday { nth, weekday })
+                    }
+                    _ => Err("monthly_nth schedules need params_json.nth between 1 and 5, or -1 to -5 counting from the end".to_string()),
+                }
+            }
+            "yearly" => {
+                let month = params
+                    .month
+                    .filter(|m| (1..=12).contains(m))
+                    .ok_or("yearly schedules need params_json.month between 1 and 12")?;
+                // 2000 is a leap year, so February 29th is accepted
+                let longest = rrule::days_in_month(NaiveDate::from_ymd_opt(2000, month, 1).unwrap());
+                match monthday {
+                    Some(day) if day == LAST_DAY_OF_MONTH || (1..=longest as i64).contains(&day) => {
+                        Ok(Recurrence::Yearly {
+                            month,
+                            monthday: day,
+                            short_month: params.short_month.unwrap_or_default(),
+                        })
+                    }
+                    _ => Err(format!(
+                        "yearly schedules need a day that exists in month {}, or {} for its last day",
+                        month, LAST_DAY_OF_MONTH
+                    )),
+                }
+            }
+            "quota" => {
+                let period = params
+                    .period
+                    .ok_or("quota schedules need params_json.period, \"week\" or \"month\"")?;
+                match params.times {
+                    Some(times) if (1..=period.length()).contains(&times) => {
+                        Ok(Recurrence::Quota { times, period })
+                    }
+                    _ => Err(format!(
+                        "quota schedules need params_json.times between 1 and {}",
+                        period.length()
+                    )),
+                }
+            }
+            "once" => {
+                let due = params.due.ok_or("once schedules need params_json.due")?;
+                let deadline = params.deadline.unwrap_or(due);
+                if due < effective_from {
+                    return Err("once schedules can't be due before they start".to_string());
+                }
+                if deadline < due {
+                    return Err("the deadline of a once schedule can't be before its due day".to_string());
+                }
+                Ok(Recurrence::Once { due, deadline })
+            }
+            "custom" => match interval_days {
+                Some(interval) if interval > 0 => match params.anchor.unwrap_or_default() {
+                    IntervalAnchor::Schedule => Ok(Recurrence::EveryNDays {
+                        interval,
+                        anchor: effective_from,
+                    }),
+                    IntervalAnchor::Completion => Ok(Recurrence::AfterCompletion {
+                        interval,
+                        start: effective_from,
+                    }),
+                },
+                _ => Err("custom schedules need an interval of at least 1 day".to_string()),
+            },
+            "rrule" => {
+                let rule: RRule = params
+                    .rrule
+                    .as_deref()
+                    .ok_or("rrule schedules need params_json.rrule")?
+                    .parse()
+                    .map_err(|e| format!("invalid RRULE: {}", e))?;
+                Ok(Recurrence::Rule {
+                    rule,
+                    start: effective_from,
+                    end: RuleEnd::default(),
+                })
+            }
+            other => Err(format!("unknown schedule type {:?}", other)),
+        }
+    }
+
+    /// For "rrule" schedules: the last day it fires on, from UNTIL or the
+    /// COUNT-th occurrence. `None` while it repeats without end.
+    fn rule_end(&self) -> Option<i64> {
+        match self {
+            Recurrence::Rule { rule, start, end } => *end
+                .0
+                .get_or_init(|| rule.end(clock::day_to_date(*start)).map(clock::date_to_day)),
+            _ => None,
+        }
+    }
+
+    /// Completions needed per period, for "quota" schedules.
+    pub fn quota(&self) -> Option<(i64, QuotaPeriod)> {
+        match *self {
+            Recurrence::Quota { times, period } => Some((times, period)),
+            _ => None,
+        }
+    }
+
+    /// Stricter checks for schedules that are about to be saved, on top of
+    /// what `parse` already enforces.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Recurrence::Rule { rule, start, .. } = self {
+            rule.validate_from(clock::day_to_date(*start))
+                .map_err(|e| format!("invalid RRULE: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, day: i64) -> bool {
+        let date = clock::day_to_date(day);
+        match self {
+            Recurrence::Daily | Recurrence::Quota { .. } => true,
+            Recurrence::Once { due, .. } => day == *due,
+            Recurrence::Weekly { weekday_mask } => {
+                let weekday = date.weekday().num_days_from_monday();
+                (weekday_mask >> weekday) & 1 == 1
+            }
+            Recurrence::Monthly {
+                monthday,
+                short_month,
+            } => is_month_day(date, *monthday, *short_month),
+            Recurrence::NthWeekday { nth, weekday } => {
+                let index = date.day0() as i64 / 7 + 1;
+                let from_end = (rrule::days_in_month(date) - date.day()) as i64 / 7 + 1;
+                date.weekday() == *weekday && (index == *nth || -from_end == *nth)
+            }
+            Recurrence::Yearly {
+                month,
+                monthday,
+                short_month,
+            } => date.month() == *month && is_month_day(date, *monthday, *short_month),
+            Recurrence::EveryNDays { interval, anchor }
+            | Recurrence::AfterCompletion {
+                interval,
+                start: anchor,
+            } => day >= *anchor && (day - anchor) % interval == 0,
+            Recurrence::Rule { rule, start, .. } => {
+                rule.matches(clock::day_to_date(*start), date)
+                    && self.rule_end().is_none_or(|end| day <= end)
+            }
+        }
+    }
+}
+
+/// Whether `date` is `monthday` of its month, see `Recurrence::Monthly`.
+fn is_month_day(date: NaiveDate, monthday: i64, short_month: ShortMonth) -> bool {
+    let day = date.day() as i64;
+    let last = rrule::days_in_month(date) as i64;
+    match monthday {
+        LAST_DAY_OF_MONTH => day == last,
+        n if n <= last => day == n,
+        _ => short_month == ShortMonth::Clamp && day == last,
+    }
+}
+
+/// Days a task was done or skipped on, for schedules whose due days depend
+/// on them.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    /// Sorted ascending.
+    pub done: Vec<i64>,
+    /// Days after this are projected as if every due day gets done on time.
+    pub today: i64,
+}
+
+impl History {
+    fn is_due_once(&self, due: i64, day: i64) -> bool {
+        match self.done.first() {
+            Some(&done) => day == done,
+            None => day == due || (day == self.today && self.today > due),
+        }
+    }
+
+    fn is_due_after_completion(&self, interval: i64, start: i64, day: i64) -> bool {
+        let index = self.done.partition_point(|&done| done < day);
+        if self.done.get(index) == Some(&day) {
+            return true;
+        }
+        let next = match index.checked_sub(1) {
+            Some(last) => self.done[last] + interval,
+            None => start,
+        };
+        if day <= self.today {
+            // Overdue stays due until done
+            day >= next
+        } else {
+            let anchor = next.max(self.today);
+            day >= anchor && (day - anchor) % interval == 0
+        }
+    }
+}
+
+/// One `task_schedule` row in evaluable form: a pattern plus the days it
+/// is in effect for.
+#[derive(Clone, Debug)]
+pub struct ScheduleVersion {
+    pub task_id: i64,
+    pub effective_from: i64,
+    pub effective_to: Option<i64>,
+    pub recurrence: Recurrence,
+    pub history: Option<History>,
+    /// Days the task is on pause, nothing is due on them.
+    pub pauses: Vec<RangeInclusive<i64>>,
+    /// Single days moved, cancelled or added, keyed by day.
+    pub exceptions: HashMap<i64, Exception>,
+}
+
+impl TryFrom<&TaskSchedule> for ScheduleVersion {
+    type Error = String;
+
+    fn try_from(schedule: &TaskSchedule) -> Result<ScheduleVersion, String> {
+        Ok(ScheduleVersion {
+            task_id: schedule.task_id,
+            effective_from: schedule.effective_from,
+            effective_to: schedule.effective_to,
+            recurrence: Recurrence::parse(
+                &schedule.type_,
+                schedule.weekday_mask,
+                schedule.monthday,
+                schedule.interval_days,
+                schedule.params_json.as_deref(),
+                schedule.effective_from,
+            )?,
+            history: None,
+            pauses: Vec::new(),
+            exceptions: HashMap::new(),
+        })
+    }
+}
+
+impl ScheduleVersion {
+    pub fn in_effect(&self, day: i64) -> bool {
+        day >= self.effective_from && self.effective_to.is_none_or(|to| day <= to)
+    }
+
+    pub fn needs_history(&self) -> bool {
+        matches!(
+            self.recurrence,
+            Recurrence::AfterCompletion { .. } | Recurrence::Once { .. }
+        )
+    }
+
+    /// For "once" schedules: the day it was done on, if it has been.
+    pub fn completed_on(&self) -> Option<i64> {
+        match self.recurrence {
+            Recurrence::Once { .. } => self.history.as_ref()?.done.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// For "once" schedules: not done and past the deadline.
+    pub fn is_overdue(&self, today: i64) -> bool {
+        match self.recurrence {
+            Recurrence::Once { deadline, .. } => self.completed_on().is_none() && today > deadline,
+            _ => false,
+        }
+    }
+
+    pub fn with_history(self, history: History) -> ScheduleVersion {
+        ScheduleVersion {
+            history: Some(history),
+            ..self
+        }
+    }
+
+    pub fn with_pauses(self, pauses: Vec<RangeInclusive<i64>>) -> ScheduleVersion {
+        ScheduleVersion { pauses, ..self }
+    }
+
+    pub fn with_exceptions(self, exceptions: HashMap<i64, Exception>) -> ScheduleVersion {
+        ScheduleVersion { exceptions, ..self }
+    }
+
+    /// Whether single occurrences can be moved: quotas have no fixed days
+    /// and a one-off is moved by changing its due day.
+    pub fn has_occurrences(&self) -> bool {
+        !matches!(
+            self.recurrence,
+            Recurrence::Quota { .. } | Recurrence::Once { .. }
+        )
+    }
+
+    pub fn is_paused(&self, day: i64) -> bool {
+        self.pauses.iter().any(|pause| pause.contains(&day))
+    }
+
+    pub fn is_due(&self, day: i64) -> bool {
+        self.in_effect(day) && !self.is_paused(day) && self.matches(day)
+    }
+
+    fn matches(&self, day: i64) -> bool {
+        match self.exceptions.get(&day) {
+            Some(Exception::Cancel) => false,
+            Some(Exception::Add) => true,
+            None => self.pattern_matches(day),
+        }
+    }
+
+    fn pattern_matches(&self, day: i64) -> bool {
+        match (&self.recurrence, &self.history) {
+            (Recurrence::AfterCompletion { interval, start }, Some(history)) => {
+                history.is_due_after_completion(*interval, *start, day)
+            }
+            (Recurrence::Once { due, .. }, Some(history)) => history.is_due_once(*due, day),
+            (recurrence, _) => recurrence.matches(day),
+        }
+    }
+
+    /// Due days within `range`, in order.
+    pub fn occurrences(&self, range: RangeInclusive<i64>) -> impl Iterator<Item = i64> + '_ {
+        self.scheduled(range).filter(|day| !self.is_paused(*day))
+    }
+
+    /// Days within `range` that would be due if the task wasn't on pause.
+    pub fn paused_occurrences(&self, range: RangeInclusive<i64>) -> impl Iterator<Item = i64> + '_ {
+        self.scheduled(range).filter(|day| self.is_paused(*day))
+    }
+
+    fn scheduled(&self, range: RangeInclusive<i64>) -> impl Iterator<Item = i64> + '_ {
+        let start = (*range.start()).max(self.effective_from);
+        let end = match self.effective_to {
+            Some(to) => (*range.end()).min(to),
+            None => *range.end(),
+        };
+        (start..=end).filter(move |day| self.matches(*day))
+    }
+
+    /// Last day anything can be due on, `None` while the schedule repeats
+    /// without end.
+    fn last_day(&self) -> Option<i64> {
+        let pattern_end = match (&self.recurrence, &self.history) {
+            (Recurrence::Once { due, .. }, Some(history)) => match history.done.first() {
+                Some(&done) => Some(done),
+                None => Some((*due).max(history.today)),
+            },
+            (Recurrence::Once { due, .. }, None) => Some(*due),
+            (Recurrence::Rule { .. }, _) => self.recurrence.rule_end(),
+            _ => None,
+        };
+        let last_added = self
+            .exceptions
+            .iter()
+            .filter(|(_, exception)| **exception == Exception::Add)
+            .map(|(day, _)| *day)
+            .max();
+        let last = match (pattern_end, last_added) {
+            (Some(end), Some(added)) => Some(end.max(added)),
+            (end, None) => end,
+            (None, Some(_)) => None,
+        };
+        match (last, self.effective_to) {
+            (Some(last), Some(to)) => Some(last.min(to)),
+            (last, to) => last.or(to),
+        }
+    }
+
+    /// First due day strictly after `day`, if the schedule has one left.
+    pub fn next_after(&self, day: i64) -> Option<i64> {
+        let mut from = day + 1;
+        // Nothing is due during a pause, one without an end never lets up
+        while let Some(pause) = self.pauses.iter().find(|pause| pause.contains(&from)) {
+            from = pause.end().checked_add(1)?;
+        }
+        let to = match self.last_day() {
+            Some(last) => last.min(from + LOOKAHEAD_DAYS),
+            None => from + LOOKAHEAD_DAYS,
+        };
+        self.occurrences(from..=to).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(y: i32, m: u32, d: u32) -> i64 {
+        clock::date_to_day(NaiveDate::from_ymd_opt(y, m, d).unwrap())
+    }
+
+    fn version(
+        type_: &str,
+        weekday_mask: Option<i64>,
+        monthday: Option<i64>,
+        interval_days: Option<i64>,
+        params_json: Option<&str>,
+        effective_from: i64,
+    ) -> ScheduleVersion {
+        ScheduleVersion::try_from(&TaskSchedule {
+            id: 1,
+            task_id: 1,
+            effective_from,
+            effective_to: None,
+            type_: type_.to_string(),
+            weekday_mask,
+            monthday,
+            interval_days,
+            params_json: params_json.map(str::to_string),
+        })
+        .unwrap()
+    }
+
+    fn due_in(version: &ScheduleVersion, from: i64, to: i64) -> Vec<NaiveDate> {
+        version
+            .occurrences(from..=to)
+            .map(clock::day_to_date)
+            .collect()
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// `is_due`, `occurrences` and `next_after` are three views of the same
+    /// set of days and must never disagree.
+    fn assert_consistent(version: &ScheduleVersion, from: i64, to: i64) {
+        let due: Vec<i64> = version.occurrences(from..=to).collect();
+        let by_day: Vec<i64> = (from..=to).filter(|d| version.is_due(*d)).collect();
+        assert_eq!(due, by_day, "{:?}", version.recurrence);
+        for d in from..to {
+            let expected = due.iter().copied().find(|due| *due > d);
+            if let Some(expected) = expected {
+                assert_eq!(
+                    version.next_after(d),
+                    Some(expected),
+                    "{:?} after {}",
+                    version.recurrence,
+                    d
+                );
+            }
+        }
+    }
+
+    /// Small xorshift so the sweep is the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, below: i64) -> i64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % below as u64) as i64
+        }
+    }
+
+    #[test]
+    fn views_agree_for_generated_schedules() {
+        let mut rng = Rng(0x5eed);
+        let base = day(2023, 12, 20);
+        for _ in 0..300 {
+            let from = base + rng.next(120);
+            let mut v = match rng.next(9) {
+                0 => version("daily", None, None, None, None, from),
+                1 => version("weekly", Some(1 + rng.next(127)), None, None, None, from),
+                2 => {
+                    let monthday = [1, 15, 28, 29, 30, 31, -1][rng.next(7) as usize];
+                    let params = ["{\"short_month\":\"skip\"}", "{\"short_month\":\"clamp\"}"]
+                        [rng.next(2) as usize];
+                    version("monthly", None, Some(monthday), None, Some(params), from)
+                }
+                3 => {
+                    let nth = [1, 2, 3, 4, 5, -1, -2, -5][rng.next(8) as usize];
+                    let params = format!("{{\"nth\":{}}}", nth);
+                    version(
+                        "monthly_nth",
+                        Some(1 << rng.next(7)),
+                        None,
+                        None,
+                        Some(&params),
+                        from,
+                    )
+                }
+                4 => version("yearly", None, Some(29), None, Some("{\"month\":2}"), from),
+                5 => version("custom", None, None, Some(1 + rng.next(20)), None, from),
+                6 => {
+                    let params = format!("{{\"due\":{}}}", from + rng.next(60));
+                    version("once", None, None, None, Some(&params), from)
+                }
+                7 => version(
+                    "rrule",
+                    None,
+                    None,
+                    None,
+                    Some("{\"rrule\":\"FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=9\"}"),
+                    from,
+                ),
+                _ => version(
+                    "rrule",
+                    None,
+                    None,
+                    None,
+                    Some("{\"rrule\":\"FREQ=MONTHLY;BYDAY=-1FR\"}"),
+                    from,
+                ),
+            };
+            if rng.next(3) == 0 {
+                let start = from + rng.next(90);
+                let end = if rng.next(2) == 0 {
+                    i64::MAX
+                } else {
+                    start + rng.next(30)
+                };
+                v = v.with_pauses(vec![start..=end]);
+            }
+            if rng.next(3) == 0 {
+                let mut exceptions = HashMap::new();
+                exceptions.insert(from + rng.next(200), Exception::Add);
+                exceptions.insert(from + rng.next(200), Exception::Cancel);
+                v = v.with_exceptions(exceptions);
+            }
+            if rng.next(4) == 0 {
+                v.effective_to = Some(from + rng.next(300));
+            }
+            assert_consistent(&v, from - 5, from + 420);
+        }
+    }
+
+    #[test]
+    fn month_end() {
+        let from = day(2023, 1, 1);
+        let clamp = version("monthly", None, Some(31), None, None, from);
+        assert_eq!(
+            due_in(&clamp, day(2023, 1, 1), day(2023, 4, 30)),
+            [
+                date(2023, 1, 31),
+                date(2023, 2, 28),
+                date(2023, 3, 31),
+                date(2023, 4, 30)
+            ]
+        );
+        let skip = version(
+            "monthly",
+            None,
+            Some(30),
+            None,
+            Some("{\"short_month\":\"skip\"}"),
+            from,
+        );
+        assert_eq!(
+            due_in(&skip, day(2023, 1, 1), day(2023, 3, 31)),
+            [date(2023, 1, 30), date(2023, 3, 30)]
+        );
+        let last = version("monthly", None, Some(LAST_DAY_OF_MONTH), None, None, from);
+        assert_eq!(
+            due_in(&last, day(2024, 1, 1), day(2024, 3, 31)),
+            [date(2024, 1, 31), date(2024, 2, 29), date(2024, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn nth_weekday() {
+        let from = day(2024, 1, 1);
+        // Fifth Monday: only months that have one
+        let fifth = version(
+            "monthly_nth",
+            Some(1),
+            None,
+            None,
+            Some("{\"nth\":5}"),
+            from,
+        );
+        assert_eq!(
+            due_in(&fifth, from, day(2024, 6, 30)),
+            [date(2024, 1, 29), date(2024, 4, 29)]
+        );
+        // Last Friday, whether the month has four or five
+        let last = version(
+            "monthly_nth",
+            Some(1 << 4),
+            None,
+            None,
+            Some("{\"nth\":-1}"),
+            from,
+        );
+        assert_eq!(
+            due_in(&last, from, day(2024, 3, 31)),
+            [date(2024, 1, 26), date(2024, 2, 23), date(2024, 3, 29)]
+        );
+        let first = version(
+            "monthly_nth",
+            Some(1 << 6),
+            None,
+            None,
+            Some("{\"nth\":1}"),
+            from,
+        );
+        assert_eq!(first.next_after(day(2024, 9, 1)), Some(day(2024, 10, 6)));
+    }
+
+    #[test]
+    fn leap_day() {
+        let from = day(2023, 1, 1);
+        let clamp = version("yearly", None, Some(29), None, Some("{\"month\":2}"), from);
+        assert_eq!(
+            due_in(&clamp, from, day(2025, 12, 31)),
+            [date(2023, 2, 28), date(2024, 2, 29), date(2025, 2, 28)]
+        );
+        let skip = version(
+            "yearly",
+            None,
+            Some(29),
+            None,
+            Some("{\"month\":2,\"short_month\":\"skip\"}"),
+            from,
+        );
+        assert_eq!(skip.next_after(day(2024, 3, 1)), Some(day(2028, 2, 29)));
+        assert!(
+            Recurrence::parse("yearly", None, Some(30), None, Some("{\"month\":2}"), from).is_err()
+        );
+    }
+
+    #[test]
+    fn rrule_count_and_until() {
+        let from = day(2024, 1, 1); // a Monday
+        let count = version(
+            "rrule",
+            None,
+            None,
+            None,
+            Some("{\"rrule\":\"FREQ=WEEKLY;BYDAY=MO,FR;COUNT=3\"}"),
+            from,
+        );
+        assert_eq!(
+            due_in(&count, from, day(2024, 12, 31)),
+            [date(2024, 1, 1), date(2024, 1, 5), date(2024, 1, 8)]
+        );
+        assert_eq!(count.next_after(day(2024, 1, 7)), Some(day(2024, 1, 8)));
+        assert_eq!(count.next_after(day(2024, 1, 8)), None);
+        assert_eq!(count.last_day(), Some(day(2024, 1, 8)));
+
+        let until = version(
+            "rrule",
+            None,
+            None,
+            None,
+            Some("{\"rrule\":\"FREQ=DAILY;INTERVAL=10;UNTIL=20240121\"}"),
+            from,
+        );
+        assert_eq!(
+            due_in(&until, from, day(2024, 12, 31)),
+            [date(2024, 1, 1), date(2024, 1, 11), date(2024, 1, 21)]
+        );
+        assert_eq!(until.next_after(day(2024, 1, 21)), None);
+    }
+
+    #[test]
+    fn next_after_stops_early() {
+        let from = day(2024, 1, 1);
+        let once = version(
+            "once",
+            None,
+            None,
+            None,
+            Some(&format!("{{\"due\":{}}}", from + 3)),
+            from,
+        );
+        assert_eq!(once.next_after(from), Some(from + 3));
+        assert_eq!(once.next_after(from + 3), None);
+        // Late and not done yet: it shows up today, then nothing is left
+        let late = once.clone().with_history(History {
+            done: vec![],
+            today: from + 10,
+        });
+        assert_eq!(late.next_after(from + 3), Some(from + 10));
+        assert_eq!(late.next_after(from + 10), None);
+        let done = once.with_history(History {
+            done: vec![from + 5],
+            today: from + 10,
+        });
+        assert_eq!(done.next_after(from + 5), None);
+
+        let daily = version("daily", None, None, None, None, from);
+        let paused = daily.clone().with_pauses(vec![from + 2..=i64::MAX]);
+        assert_eq!(paused.next_after(from), Some(from + 1));
+        assert_eq!(paused.next_after(from + 1), None);
+        let vacation = daily.with_pauses(vec![from + 2..=from + 9, from + 10..=from + 12]);
+        assert_eq!(vacation.next_after(from + 1), Some(from + 13));
+
+        // An added day outlives the rule that ended
+        let count = version(
+            "rrule",
+            None,
+            None,
+            None,
+            Some("{\"rrule\":\"FREQ=DAILY;COUNT=2\"}"),
+            from,
+        );
+        let extra = count.with_exceptions(HashMap::from([(from + 50, Exception::Add)]));
+        assert_eq!(extra.next_after(from + 1), Some(from + 50));
+        assert_eq!(extra.next_after(from + 50), None);
+    }
+
+    #[test]
+    fn after_completion() {
+        let from = day(2024, 1, 1);
+        let v = version(
+            "custom",
+            None,
+            None,
+            Some(3),
+            Some("{\"anchor\":\"completion\"}"),
+            from,
+        );
+        let v = v.with_history(History {
+            done: vec![from + 1],
+            today: from + 6,
+        });
+        // Due 3 days after it was done and every day until done again
+        assert!(!v.is_due(from + 3));
+        assert!(v.is_due(from + 4));
+        assert!(v.is_due(from + 6));
+        // Past today it projects on-time completions
+        assert_eq!(v.next_after(from + 6), Some(from + 9));
+        assert_consistent(&v, from, from + 40);
+    }
+
+    #[test]
+    fn rule_end_is_only_worked_out_when_needed() {
+        let from = day(2024, 1, 1);
+        let v = version(
+            "rrule",
+            None,
+            None,
+            None,
+            Some("{\"rrule\":\"FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29;COUNT=50\"}"),
+            from,
+        );
+        let Recurrence::Rule { end, .. } = &v.recurrence else {
+            panic!("not a rule");
+        };
+        assert_eq!(end.0.get(), None);
+        // Days the rule doesn't hit never need the end
+        assert!(!v.is_due(from));
+        assert_eq!(end.0.get(), None);
+        assert!(v.is_due(day(2024, 2, 29)));
+        // 2100 and 2200 aren't leap years
+        assert_eq!(end.0.get(), Some(&Some(day(2228, 2, 29))));
+    }
+}
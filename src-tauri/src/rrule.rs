@@ -0,0 +1,624 @@
+//! The date-only subset of RFC 5545 recurrence rules that makes sense for
+//! habits: FREQ (DAILY to YEARLY), INTERVAL, BYDAY with ordinals, BYMONTHDAY
+//! (negative counts from the end of the month), BYMONTH, COUNT, UNTIL and WKST.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+
+/// How far ahead COUNT and "is there any occurrence at all" are searched.
+const SEARCH_LIMIT_DAYS: i64 = 400 * 366;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// One BYDAY entry, e.g. `TU`, `2TU` or `-1FR`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByDay {
+    pub ordinal: Option<i32>,
+    pub weekday: Weekday,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RRule {
+    pub freq: Freq,
+    pub interval: u32,
+    pub by_day: Vec<ByDay>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDate>,
+    pub week_start: Weekday,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RRuleError(String);
+
+impl fmt::Display for RRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RRuleError {}
+
+fn err<T>(msg: impl Into<String>) -> Result<T, RRuleError> {
+    Err(RRuleError(msg.into()))
+}
+
+impl FromStr for RRule {
+    type Err = RRuleError;
+
+    fn from_str(s: &str) -> Result<RRule, RRuleError> {
+        let s = s.trim();
+        let body = match s.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("RRULE:") => &s[6..],
+            _ => s,
+        };
+
+        let mut freq = None;
+        let mut interval = None;
+        let mut by_day = None;
+        let mut by_month_day = None;
+        let mut by_month = None;
+        let mut count = None;
+        let mut until = None;
+        let mut week_start = None;
+
+        for part in body.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| RRuleError(format!("expected KEY=VALUE, got {:?}", part)))?;
+            let key = key.trim().to_ascii_uppercase();
+            let value = value.trim().to_ascii_uppercase();
+
+            fn set<T>(slot: &mut Option<T>, key: &str, value: T) -> Result<(), RRuleError> {
+                if slot.replace(value).is_some() {
+                    return err(format!("{} is given more than once", key));
+                }
+                Ok(())
+            }
+
+            match key.as_str() {
+                "FREQ" => set(&mut freq, &key, parse_freq(&value)?)?,
+                "INTERVAL" => set(&mut interval, &key, parse_positive(&key, &value)?)?,
+                "COUNT" => set(&mut count, &key, parse_positive(&key, &value)?)?,
+                "UNTIL" => set(&mut until, &key, parse_until(&value)?)?,
+                "WKST" => set(&mut week_start, &key, parse_weekday(&value)?)?,
+                "BYDAY" => set(&mut by_day, &key, parse_list(&value, parse_by_day)?)?,
+                "BYMONTHDAY" => set(
+                    &mut by_month_day,
+                    &key,
+                    parse_list(&value, parse_month_day)?,
+                )?,
+                "BYMONTH" => set(&mut by_month, &key, parse_list(&value, parse_month)?)?,
+                "BYSETPOS" | "BYYEARDAY" | "BYWEEKNO" | "BYHOUR" | "BYMINUTE" | "BYSECOND" => {
+                    return err(format!("{} is not supported", key));
+                }
+                _ => return err(format!("unknown rule part {}", key)),
+            }
+        }
+
+        let rule = RRule {
+            freq: freq.ok_or_else(|| RRuleError("FREQ is required".to_string()))?,
+            interval: interval.unwrap_or(1),
+            by_day: by_day.unwrap_or_default(),
+            by_month_day: by_month_day.unwrap_or_default(),
+            by_month: by_month.unwrap_or_default(),
+            count,
+            until,
+            week_start: week_start.unwrap_or(Weekday::Mon),
+        };
+        rule.check()?;
+        Ok(rule)
+    }
+}
+
+impl RRule {
+    /// Combinations RFC 5545 rules out, or that this subset can't express.
+    fn check(&self) -> Result<(), RRuleError> {
+        if self.count.is_some() && self.until.is_some() {
+            return err("COUNT and UNTIL can't be combined");
+        }
+        if self.freq == Freq::Weekly && !self.by_month_day.is_empty() {
+            return err("BYMONTHDAY can't be used with FREQ=WEEKLY");
+        }
+        // Ordinals pick the nth weekday of a month, or of the year when
+        // a yearly rule has no BYMONTH
+        let max_ordinal = match self.freq {
+            Freq::Daily | Freq::Weekly => 0,
+            Freq::Monthly => 5,
+            Freq::Yearly if self.by_month.is_empty() => 53,
+            Freq::Yearly => 5,
+        };
+        for by_day in &self.by_day {
+            if let Some(n) = by_day.ordinal {
+                if max_ordinal == 0 {
+                    return err(format!(
+                        "BYDAY ordinals like {}{} need FREQ=MONTHLY or FREQ=YEARLY",
+                        n,
+                        weekday_code(by_day.weekday)
+                    ));
+                }
+                if n.abs() > max_ordinal {
+                    return err(format!(
+                        "BYDAY ordinal {} is out of range, must be between -{} and {}",
+                        n, max_ordinal, max_ordinal
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects rules that never fire from `start` on, so a schedule can't
+    /// silently end up with nothing due.
+    pub fn validate_from(&self, start: NaiveDate) -> Result<(), RRuleError> {
+        if let Some(until) = self.until {
+            if until < start {
+                return err(format!("UNTIL {} is before the schedule starts", until));
+            }
+        }
+        match self.nth_occurrence(start, 1) {
+            Some(first) if self.until.is_none_or(|until| first <= until) => Ok(()),
+            _ => err("rule never produces an occurrence"),
+        }
+    }
+
+    /// Last date the rule can fire on, from UNTIL or the COUNT-th occurrence.
+    /// `None` for open ended rules.
+    pub fn end(&self, start: NaiveDate) -> Option<NaiveDate> {
+        match self.count {
+            // A COUNT that runs past the search window never ends in practice
+            Some(count) => Some(
+                self.nth_occurrence(start, count)
+                    .unwrap_or(start + chrono::Duration::days(SEARCH_LIMIT_DAYS)),
+            ),
+            None => self.until,
+        }
+    }
+
+    /// The `n`th (1-based) occurrence, ignoring COUNT and UNTIL.
+    fn nth_occurrence(&self, start: NaiveDate, n: u32) -> Option<NaiveDate> {
+        let mut seen = 0;
+        (0..SEARCH_LIMIT_DAYS)
+            .map(|offset| start + chrono::Duration::days(offset))
+            .find(|date| {
+                if self.matches(start, *date) {
+                    seen += 1;
+                }
+                seen == n
+            })
+    }
+
+    /// The rule as a predicate, without the COUNT and UNTIL limits.
+    pub fn matches(&self, start: NaiveDate, date: NaiveDate) -> bool {
+        if date < start || !self.in_interval(start, date) {
+            return false;
+        }
+
+        let defaults_to_start_day =
+            self.by_day.is_empty() && matches!(self.freq, Freq::Monthly | Freq::Yearly);
+
+        if !self.by_month.is_empty() {
+            if !self.by_month.contains(&date.month()) {
+                return false;
+            }
+        } else if self.freq == Freq::Yearly
+            && self.by_month_day.is_empty()
+            && self.by_day.is_empty()
+            && date.month() != start.month()
+        {
+            return false;
+        }
+
+        if !self.by_month_day.is_empty() {
+            if !self.by_month_day.iter().any(|&md| month_day_is(date, md)) {
+                return false;
+            }
+        } else if defaults_to_start_day && date.day() != start.day() {
+            return false;
+        }
+
+        if !self.by_day.is_empty() {
+            if !self.by_day.iter().any(|bd| self.by_day_is(bd, date)) {
+                return false;
+            }
+        } else if self.freq == Freq::Weekly && date.weekday() != start.weekday() {
+            return false;
+        }
+
+        true
+    }
+
+    fn in_interval(&self, start: NaiveDate, date: NaiveDate) -> bool {
+        let interval = self.interval as i64;
+        let periods = match self.freq {
+            Freq::Daily => (date - start).num_days(),
+            Freq::Weekly => {
+                let first_week = start
+                    - chrono::Duration::days(start.weekday().days_since(self.week_start) as i64);
+                (date - first_week).num_days().div_euclid(7)
+            }
+            Freq::Monthly => {
+                (date.year() as i64 * 12 + date.month0() as i64)
+                    - (start.year() as i64 * 12 + start.month0() as i64)
+            }
+            Freq::Yearly => (date.year() - start.year()) as i64,
+        };
+        periods % interval == 0
+    }
+
+    fn by_day_is(&self, by_day: &ByDay, date: NaiveDate) -> bool {
+        if date.weekday() != by_day.weekday {
+            return false;
+        }
+        let Some(n) = by_day.ordinal else {
+            return true;
+        };
+        let in_month = self.freq == Freq::Monthly || !self.by_month.is_empty();
+        let (index, length) = if in_month {
+            (date.day0() as i32, days_in_month(date) as i32)
+        } else {
+            (date.ordinal0() as i32, days_in_year(date) as i32)
+        };
+        if n > 0 {
+            index / 7 + 1 == n
+        } else {
+            (length - 1 - index) / 7 + 1 == -n
+        }
+    }
+}
+
+/// `md` is 1..=31 counted from the start of the month or -31..=-1 from its end.
+fn month_day_is(date: NaiveDate, md: i32) -> bool {
+    let day = date.day() as i32;
+    if md > 0 {
+        day == md
+    } else {
+        days_in_month(date) as i32 + md + 1 == day
+    }
+}
+
+pub fn days_in_month(date: NaiveDate) -> u32 {
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        m => (date.year(), m + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|next| next.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+fn days_in_year(date: NaiveDate) -> u32 {
+    if date.leap_year() {
+        366
+    } else {
+        365
+    }
+}
+
+fn parse_freq(value: &str) -> Result<Freq, RRuleError> {
+    match value {
+        "DAILY" => Ok(Freq::Daily),
+        "WEEKLY" => Ok(Freq::Weekly),
+        "MONTHLY" => Ok(Freq::Monthly),
+        "YEARLY" => Ok(Freq::Yearly),
+        "HOURLY" | "MINUTELY" | "SECONDLY" => err(format!(
+            "FREQ={} is not supported, habits repeat at most daily",
+            value
+        )),
+        _ => err(format!("unknown FREQ {:?}", value)),
+    }
+}
+
+fn parse_positive(key: &str, value: &str) -> Result<u32, RRuleError> {
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => err(format!(
+            "{} must be a positive whole number, got {:?}",
+            key, value
+        )),
+    }
+}
+
+/// UNTIL may be a DATE (`20250131`) or DATE-TIME (`20250131T235959Z`), only
+/// the date matters here.
+fn parse_until(value: &str) -> Result<NaiveDate, RRuleError> {
+    let date = value.split('T').next().unwrap_or(value);
+    NaiveDate::parse_from_str(date, "%Y%m%d")
+        .or_else(|_| err(format!("UNTIL must look like 20250131, got {:?}", value)))
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday, RRuleError> {
+    match value {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => err(format!("unknown weekday {:?}, expected MO..SU", value)),
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn parse_by_day(value: &str) -> Result<ByDay, RRuleError> {
+    let split = value.len().saturating_sub(2);
+    let (ordinal, day) = match (value.get(..split), value.get(split..)) {
+        (Some(ordinal), Some(day)) => (ordinal, day),
+        _ => return err(format!("invalid BYDAY entry {:?}", value)),
+    };
+    let weekday = parse_weekday(day)?;
+    let ordinal = match ordinal {
+        "" => None,
+        n => match n.trim_start_matches('+').parse::<i32>() {
+            Ok(0) | Err(_) => {
+                return err(format!("invalid BYDAY ordinal in {:?}", value));
+            }
+            Ok(n) => Some(n),
+        },
+    };
+    Ok(ByDay { ordinal, weekday })
+}
+
+fn parse_month_day(value: &str) -> Result<i32, RRuleError> {
+    match value.parse::<i32>() {
+        Ok(n) if n != 0 && (-31..=31).contains(&n) => Ok(n),
+        _ => err(format!(
+            "BYMONTHDAY must be between 1 and 31 or -31 and -1, got {:?}",
+            value
+        )),
+    }
+}
+
+fn parse_month(value: &str) -> Result<u32, RRuleError> {
+    match value.parse::<u32>() {
+        Ok(n) if (1..=12).contains(&n) => Ok(n),
+        _ => err(format!("BYMONTH must be between 1 and 12, got {:?}", value)),
+    }
+}
+
+fn parse_list<T>(
+    value: &str,
+    parse: impl Fn(&str) -> Result<T, RRuleError>,
+) -> Result<Vec<T>, RRuleError> {
+    value.split(',').map(|v| parse(v.trim())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn rule(s: &str) -> RRule {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    fn rejected(s: &str) -> String {
+        s.parse::<RRule>().unwrap_err().to_string()
+    }
+
+    /// Occurrences between `from` and `to`, COUNT and UNTIL included.
+    fn between(rule: &RRule, start: NaiveDate, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let end = rule.end(start);
+        from.iter_days()
+            .take_while(|d| *d <= to)
+            .filter(|d| rule.matches(start, *d) && end.is_none_or(|end| *d <= end))
+            .collect()
+    }
+
+    #[test]
+    fn parses_the_supported_parts() {
+        let r = rule("RRULE:freq=monthly;interval=2;byday=2TU,-1fr;COUNT=5;WKST=SU");
+        assert_eq!(r.freq, Freq::Monthly);
+        assert_eq!(r.interval, 2);
+        assert_eq!(
+            r.by_day,
+            [
+                ByDay {
+                    ordinal: Some(2),
+                    weekday: Weekday::Tue
+                },
+                ByDay {
+                    ordinal: Some(-1),
+                    weekday: Weekday::Fri
+                },
+            ]
+        );
+        assert_eq!(r.count, Some(5));
+        assert_eq!(r.week_start, Weekday::Sun);
+
+        let r = rule("FREQ=YEARLY;BYMONTH=2,8;BYMONTHDAY=-1;UNTIL=20300101T000000Z");
+        assert_eq!(r.by_month, [2, 8]);
+        assert_eq!(r.by_month_day, [-1]);
+        assert_eq!(r.until, Some(date(2030, 1, 1)));
+        assert_eq!(r.interval, 1);
+        assert_eq!(r.week_start, Weekday::Mon);
+    }
+
+    #[test]
+    fn ordinal_weekdays() {
+        let start = date(2025, 1, 1);
+        let second_tuesday = rule("FREQ=MONTHLY;BYDAY=2TU");
+        assert_eq!(
+            between(&second_tuesday, start, start, date(2025, 3, 31)),
+            [date(2025, 1, 14), date(2025, 2, 11), date(2025, 3, 11)]
+        );
+        let last_friday = rule("FREQ=MONTHLY;BYDAY=-1FR");
+        assert_eq!(
+            between(&last_friday, start, start, date(2025, 3, 31)),
+            [date(2025, 1, 31), date(2025, 2, 28), date(2025, 3, 28)]
+        );
+        // Without BYMONTH a yearly ordinal counts through the whole year
+        let last_monday = rule("FREQ=YEARLY;BYDAY=-1MO");
+        assert_eq!(
+            between(&last_monday, start, start, date(2026, 12, 31)),
+            [date(2025, 12, 29), date(2026, 12, 28)]
+        );
+    }
+
+    #[test]
+    fn last_day_of_the_month() {
+        let r = rule("FREQ=MONTHLY;BYMONTHDAY=-1");
+        let start = date(2024, 1, 15);
+        assert_eq!(
+            between(&r, start, start, date(2024, 4, 30)),
+            [
+                date(2024, 1, 31),
+                date(2024, 2, 29),
+                date(2024, 3, 31),
+                date(2024, 4, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn by_month() {
+        let r = rule("FREQ=YEARLY;BYMONTH=3,9;BYMONTHDAY=1");
+        let start = date(2024, 1, 1);
+        assert_eq!(
+            between(&r, start, start, date(2025, 6, 30)),
+            [date(2024, 3, 1), date(2024, 9, 1), date(2025, 3, 1)]
+        );
+        // BYMONTH narrows a daily rule down to those months
+        let r = rule("FREQ=DAILY;BYMONTH=2");
+        assert!(r.matches(start, date(2024, 2, 10)));
+        assert!(!r.matches(start, date(2024, 3, 10)));
+    }
+
+    #[test]
+    fn count_and_until() {
+        let start = date(2025, 1, 6);
+        let r = rule("FREQ=WEEKLY;BYDAY=MO,TH;COUNT=3");
+        assert_eq!(r.end(start), Some(date(2025, 1, 13)));
+        assert_eq!(
+            between(&r, start, start, date(2025, 2, 28)),
+            [date(2025, 1, 6), date(2025, 1, 9), date(2025, 1, 13)]
+        );
+        let r = rule("FREQ=DAILY;INTERVAL=3;UNTIL=20250115");
+        assert_eq!(r.end(start), Some(date(2025, 1, 15)));
+        assert_eq!(
+            between(&r, start, start, date(2025, 2, 28)),
+            [
+                date(2025, 1, 6),
+                date(2025, 1, 9),
+                date(2025, 1, 12),
+                date(2025, 1, 15)
+            ]
+        );
+        assert_eq!(rule("FREQ=DAILY").end(start), None);
+    }
+
+    #[test]
+    fn unsupported_parts_are_named() {
+        assert_eq!(
+            rejected("FREQ=MONTHLY;BYDAY=MO;BYSETPOS=-1"),
+            "BYSETPOS is not supported"
+        );
+        assert_eq!(
+            rejected("FREQ=HOURLY"),
+            "FREQ=HOURLY is not supported, habits repeat at most daily"
+        );
+        assert_eq!(rejected("FREQ=FORTNIGHTLY"), "unknown FREQ \"FORTNIGHTLY\"");
+        assert_eq!(rejected("FREQ=DAILY;COLOR=RED"), "unknown rule part COLOR");
+        assert_eq!(rejected("INTERVAL=2"), "FREQ is required");
+        assert_eq!(
+            rejected("FREQ=DAILY;FREQ=WEEKLY"),
+            "FREQ is given more than once"
+        );
+        assert_eq!(rejected("FREQ"), "expected KEY=VALUE, got \"FREQ\"");
+    }
+
+    #[test]
+    fn bad_values_are_explained() {
+        assert_eq!(
+            rejected("FREQ=MONTHLY;BYDAY=0TU"),
+            "invalid BYDAY ordinal in \"0TU\""
+        );
+        assert_eq!(
+            rejected("FREQ=MONTHLY;BYDAY=6TU"),
+            "BYDAY ordinal 6 is out of range, must be between -5 and 5"
+        );
+        assert_eq!(
+            rejected("FREQ=WEEKLY;BYDAY=2TU"),
+            "BYDAY ordinals like 2TU need FREQ=MONTHLY or FREQ=YEARLY"
+        );
+        assert_eq!(
+            rejected("FREQ=MONTHLY;BYDAY=XX"),
+            "unknown weekday \"XX\", expected MO..SU"
+        );
+        for md in ["0", "32", "-32"] {
+            assert_eq!(
+                rejected(&format!("FREQ=MONTHLY;BYMONTHDAY={}", md)),
+                format!(
+                    "BYMONTHDAY must be between 1 and 31 or -31 and -1, got {:?}",
+                    md
+                )
+            );
+        }
+        assert_eq!(
+            rejected("FREQ=YEARLY;BYMONTH=13"),
+            "BYMONTH must be between 1 and 12, got \"13\""
+        );
+        assert_eq!(
+            rejected("FREQ=DAILY;COUNT=0"),
+            "COUNT must be a positive whole number, got \"0\""
+        );
+        assert_eq!(
+            rejected("FREQ=DAILY;UNTIL=2025-01-31"),
+            "UNTIL must look like 20250131, got \"2025-01-31\""
+        );
+        assert_eq!(
+            rejected("FREQ=DAILY;COUNT=2;UNTIL=20250131"),
+            "COUNT and UNTIL can't be combined"
+        );
+        assert_eq!(
+            rejected("FREQ=WEEKLY;BYMONTHDAY=1"),
+            "BYMONTHDAY can't be used with FREQ=WEEKLY"
+        );
+    }
+
+    #[test]
+    fn rules_that_never_fire_are_rejected() {
+        let start = date(2025, 3, 1);
+        assert_eq!(
+            rule("FREQ=DAILY;UNTIL=20250201")
+                .validate_from(start)
+                .unwrap_err()
+                .to_string(),
+            "UNTIL 2025-02-01 is before the schedule starts"
+        );
+        assert_eq!(
+            rule("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30")
+                .validate_from(start)
+                .unwrap_err()
+                .to_string(),
+            "rule never produces an occurrence"
+        );
+        assert!(rule("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29")
+            .validate_from(start)
+            .is_ok());
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::config::ConfigState;
+use crate::db::{self, AppState};
+
+/// Event the frontend listens on for replica sync progress.
+pub const SYNC_STATUS_EVENT: &str = "sync-status";
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SyncStatus {
+    Syncing,
+    Synced {
+        frame_no: Option<u64>,
+        frames_synced: usize,
+        at: i64,
+    },
+    Failed {
+        error: String,
+        at: i64,
+    },
+}
+
+/// Pulls the latest frames from the primary and reports every step as a
+/// `sync-status` event. Only meaningful for the embedded replica backend.
+pub async fn sync_now(app: &AppHandle) -> Result<SyncStatus, String> {
+    let state = app.state::<AppState>();
+    emit_status(app, SyncStatus::Syncing);
+
+    let synced = match state.database.sync().await {
+        Ok(replicated) => finish_setup(app).await.map(|()| replicated),
+        Err(e) => Err(e.to_string()),
+    };
+    let status = match synced {
+        Ok(replicated) => SyncStatus::Synced {
+            frame_no: replicated.frame_no(),
+            frames_synced: replicated.frames_synced(),
+            at: Utc::now().timestamp(),
+        },
+        Err(error) => SyncStatus::Failed {
+            error,
+            at: Utc::now().timestamp(),
+        },
+    };
+    emit_status(app, status.clone());
+
+    match status {
+        SyncStatus::Failed { error, .. } => Err(error),
+        status => Ok(status),
+    }
+}
+
+/// Runs the startup steps a replica that was started offline had to skip,
+/// once a sync reached the primary. Nothing to do otherwise.
+async fn finish_setup(app: &AppHandle) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    if state.sync_required.lock().unwrap().is_none() {
+        return Ok(());
+    }
+    let config = app.state::<ConfigState>();
+    let clock = config.day_clock().await.map_err(|e| e.to_string())?;
+    let week_start = config.week_start().await;
+    let trash_retention_days = config.effective.read().await.trash_retention_days;
+
+    let conn = state.db.lock().await;
+    crate::migrations::run(&conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    // Schema writes went to the primary, bring them back locally
+    state.database.sync().await.map_err(|e| e.to_string())?;
+    db::check_schema(&conn).await.map_err(|e| e.to_string())?;
+    db::prepare(&conn, &clock, week_start, trash_retention_days)
+        .await
+        .map_err(|e| e.to_string())?;
+    *state.sync_required.lock().unwrap() = None;
+    Ok(())
+}
+
+/// Syncs in the background every `every` for as long as the app runs.
+pub fn spawn_periodic_sync(app: AppHandle, every: Duration) {
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        // The first tick fires immediately and init_db has just synced
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(e) = sync_now(&app).await {
+                eprintln!("background sync failed: {}", e);
+            }
+        }
+    });
+}
+
+fn emit_status(app: &AppHandle, status: SyncStatus) {
+    if let Err(e) = app.emit(SYNC_STATUS_EVENT, status) {
+        eprintln!("failed to emit sync status: {:?}", e);
+    }
+}
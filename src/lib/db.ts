@@ -1,19 +1,25 @@
 import { invoke } from '@tauri-apps/api/core';
-import type { TaskWithStats, MonthViewDay } from './types';
+import type { TaskWithStats, MonthViewDay, SyncStatus, Settings, CompletionStatus, Pause } from './types';
 
 export async function addTask(
     title: string,
     frequencyType: string,
     weekdayMask: number | null,
     monthday: number | null,
-    intervalDays: number | null
+    intervalDays: number | null,
+    paramsJson: string | null = null,
+    targetAmount: number | null = null,
+    targetUnit: string | null = null
 ): Promise<void> {
     await invoke('add_task', {
         title,
         frequencyType,
         weekdayMask,
         monthday,
-        intervalDays
+        intervalDays,
+        paramsJson,
+        targetAmount,
+        targetUnit
     });
 }
 
@@ -25,8 +31,32 @@ export async function deleteTask(taskId: number): Promise<void> {
     await invoke('delete_task', { taskId });
 }
 
-export async function deleteAllTasks(): Promise<void> {
-    await invoke("delete_all_tasks");
+// Archived tasks keep their history but are no longer due
+export async function archiveTask(taskId: number): Promise<void> {
+    await invoke('archive_task', { taskId });
+}
+
+export async function unarchiveTask(taskId: number): Promise<void> {
+    await invoke('unarchive_task', { taskId });
+}
+
+export async function listArchivedTasks(): Promise<TaskWithStats[]> {
+    return await invoke('list_archived_tasks');
+}
+
+// Must match DELETE_ALL_CONFIRMATION in the backend
+export const DELETE_ALL_CONFIRMATION = 'delete all tasks';
+
+export async function deleteAllTasks(confirmation: string): Promise<void> {
+    await invoke("delete_all_tasks", { confirmation });
+}
+
+export async function restoreFromTrash(taskId: number): Promise<void> {
+    await invoke('restore_from_trash', { taskId });
+}
+
+export async function listTrash(): Promise<TaskWithStats[]> {
+    return await invoke('list_trash');
 }
 
 export async function getWeeklyStreak(): Promise<number> {
@@ -37,6 +67,18 @@ export async function toggleCompletion(taskId: number, day: number): Promise<voi
     await invoke('toggle_completion', { taskId, day });
 }
 
+export async function setCompletionStatus(
+    taskId: number,
+    day: number,
+    status: CompletionStatus
+): Promise<void> {
+    await invoke('set_completion_status', { taskId, day, status });
+}
+
+export async function incrementProgress(taskId: number, day: number, amount: number): Promise<void> {
+    await invoke('increment_progress', { taskId, day, amount });
+}
+
 export async function getMonthView(year: number, month: number): Promise<MonthViewDay[]> {
     return await invoke('get_month_view', { year, month });
 }
@@ -47,7 +89,10 @@ export async function editTask(
     newFrequencyType: string,
     newWeekdayMask: number | null,
     newMonthday: number | null,
-    newIntervalDays: number | null
+    newIntervalDays: number | null,
+    newParamsJson: string | null = null,
+    newTargetAmount: number | null = null,
+    newTargetUnit: string | null = null
 ): Promise<void> {
     await invoke('edit_task', {
         taskId,
@@ -55,6 +100,74 @@ export async function editTask(
         newFrequencyType,
         newWeekdayMask,
         newMonthday,
-        newIntervalDays
+        newIntervalDays,
+        newParamsJson,
+        newTargetAmount,
+        newTargetUnit
     });
 }
+
+// Single occurrences, the rule itself stays as it is
+export async function cancelOccurrence(taskId: number, day: number): Promise<void> {
+    await invoke('cancel_occurrence', { taskId, day });
+}
+
+export async function addOccurrence(taskId: number, day: number): Promise<void> {
+    await invoke('add_occurrence', { taskId, day });
+}
+
+export async function moveOccurrence(taskId: number, fromDay: number, toDay: number): Promise<void> {
+    await invoke('move_occurrence', { taskId, fromDay, toDay });
+}
+
+export async function restoreOccurrence(taskId: number, day: number): Promise<void> {
+    await invoke('restore_occurrence', { taskId, day });
+}
+
+// Pauses every task when taskId is null; without an end it runs until ended
+export async function schedulePause(
+    taskId: number | null,
+    startDay: number,
+    endDay: number | null = null
+): Promise<number> {
+    return await invoke('schedule_pause', { taskId, startDay, endDay });
+}
+
+export async function endPause(pauseId: number): Promise<void> {
+    await invoke('end_pause', { pauseId });
+}
+
+export async function listPauses(): Promise<Pause[]> {
+    return await invoke('list_pauses');
+}
+
+// Rebuilds streaks from the full history, for one task or all when taskId is null
+export async function recomputeStats(taskId: number | null = null): Promise<void> {
+    await invoke('recompute_stats', { taskId });
+}
+
+// Reverts the latest change of this session, false when there is nothing to undo
+export async function undo(): Promise<boolean> {
+    return await invoke('undo');
+}
+
+export async function redo(): Promise<boolean> {
+    return await invoke('redo');
+}
+
+export async function syncNow(): Promise<SyncStatus> {
+    return await invoke('sync_now');
+}
+
+// Today's day index as the backend sees it, honouring timezone and day start hour
+export async function getToday(): Promise<number> {
+    return await invoke('get_today');
+}
+
+export async function getSettings(): Promise<Settings> {
+    return await invoke('get_settings');
+}
+
+export async function updateSettings(settings: Settings): Promise<void> {
+    await invoke('update_settings', { settings });
+}
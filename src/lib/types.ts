@@ -5,6 +5,9 @@ export interface Task {
     is_active: boolean;
     created_at: number;
     archived_at: number | null;
+    target_amount: number | null;
+    target_unit: string | null;
+    deleted_at: number | null; // set while the task is in the trash
 }
 
 export interface TaskSchedule {
@@ -12,31 +15,92 @@ export interface TaskSchedule {
     task_id: number;
     effective_from: number;
     effective_to: number | null;
-    type: 'daily' | 'weekly' | 'monthly' | 'custom';
+    type: 'daily' | 'weekly' | 'monthly' | 'monthly_nth' | 'yearly' | 'quota' | 'once' | 'custom' | 'rrule';
     weekday_mask: number | null;
-    monthday: number | null;
+    monthday: number | null; // 1-31, -1 for the last day of the month
     interval_days: number | null;
-    params_json: string | null;
+    params_json: string | null; // e.g. {"rrule": "FREQ=MONTHLY;BYDAY=2TU"}, {"short_month": "skip"}, {"nth": -1}, {"month": 3}, {"times": 3, "period": "week"}
 }
 
+export type CompletionStatus = 'cleared' | 'in_progress' | 'done' | 'skipped';
+
 export interface TaskWithStats {
     task: Task;
     schedule: TaskSchedule;
     current_streak: number;
     best_streak: number;
     today_status: boolean;
+    status: CompletionStatus;
+    progress: number | null;
+    is_due: boolean;
+    next_due: number | null; // day index of the next due day after the listed one
+    quota: QuotaProgress | null;
+    overdue: boolean; // one-off past its deadline
+    paused: boolean;
+    freezes: number; // streak freezes left to cover a missed day
+}
+
+export interface QuotaProgress {
+    done: number;
+    times: number;
+    period_start: number;
+    period_end: number;
 }
 
 export interface MonthTask {
     id: number;
     title: string;
     is_done: boolean;
+    status: CompletionStatus;
+    progress: number | null;
+    target: number | null;
+    frozen: boolean; // missed, but a streak freeze covered it
 }
 
 export interface MonthViewDay {
     day: number;
     due_count: number;
     done_count: number;
+    skipped_count: number;
     all_done: boolean;
+    progress: number;
     tasks: MonthTask[];
+    paused: boolean; // a pause for every task covers the day
+    paused_count: number;
+}
+
+export interface Pause {
+    id: number;
+    task_id: number | null; // null pauses every task
+    start_day: number;
+    end_day: number | null; // inclusive, null until it is ended
+}
+
+// Payload of the `sync-status` event and the `sync_now` command
+export type SyncStatus =
+    | { state: 'syncing' }
+    | { state: 'synced'; frame_no: number | null; frames_synced: number; at: number }
+    | { state: 'failed'; error: string; at: number };
+
+export interface DatabaseSettings {
+    mode: 'local' | 'remote' | 'replica';
+    url: string | null;
+    auth_token: string | null;
+    path: string | null;
+}
+
+export interface Profile {
+    timezone: string | null;
+    day_start_hour: number | null;
+}
+
+export interface Settings {
+    database: DatabaseSettings;
+    sync_interval_secs: number;
+    week_start: 'monday' | 'sunday';
+    timezone: string | null;
+    day_start_hour: number;
+    trash_retention_days: number; // 0 keeps deleted tasks until restored
+    profile: string | null; // key into profiles, replaces timezone and day_start_hour
+    profiles: Record<string, Profile>;
 }